@@ -0,0 +1,148 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use grammers_client::{Client, InvocationError};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    audit::{AuditEntry, record},
+    check::{CommonGroup, group_ids},
+    config::MainConfig,
+    gban::{BanEntry, BanList},
+    history::track,
+    http::{Request, Response, serve},
+    pool::{NO_USERBOT_ERROR, Pool},
+    risk::Assessment,
+    shutdown::InFlight,
+};
+
+/// The REST API is off unless `addr` is set; requests need one of `tokens` as a bearer token.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiConfig {
+    pub addr: Option<String>,
+    #[serde(default)]
+    pub tokens: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct CommonGroups {
+    user: i64,
+    username: Option<String>,
+    count: usize,
+    groups: Vec<CommonGroup>,
+    /// Monitored groups no available userbot could answer for, so `groups` may be incomplete.
+    missed: usize,
+    risk: Option<Assessment>,
+    ban: Option<BanEntry>,
+}
+
+#[derive(Serialize)]
+struct ApiError {
+    error: String,
+}
+
+fn error(status: u16, e: impl ToString) -> Response {
+    Response::json(
+        status,
+        &ApiError {
+            error: e.to_string(),
+        },
+    )
+}
+
+/// Looks `target` up like `/check` with full detail, for callers outside Telegram.
+/// Failures come with the HTTP status that describes them.
+pub async fn lookup(
+    pool: &Pool,
+    bot: &Client,
+    config: &MainConfig,
+    target: &str,
+    command: &str,
+) -> std::result::Result<CommonGroups, (u16, anyhow::Error)> {
+    let _running = InFlight::start();
+    let started = Instant::now();
+    let user = pool.resolve(target).await.map_err(|e| {
+        let status = match e.downcast_ref::<InvocationError>() {
+            _ if !target.starts_with('@') => 400,
+            Some(rpc) if rpc.is("USERNAME_INVALID") => 400,
+            Some(_) => 502,
+            None if e.to_string() == NO_USERBOT_ERROR => 502,
+            None => 404,
+        };
+        (status, e)
+    })?;
+    match pool.is_self(bot, user).await {
+        Ok(false) => {}
+        Ok(true) => return Err((400, anyhow::anyhow!("不能查询自身"))),
+        Err(e) => return Err((502, e)),
+    }
+    let lookup = pool
+        .common_groups(user, &config.groups)
+        .await
+        .map_err(|e| (502, e))?;
+    track(user, &lookup);
+    let list = lookup.groups;
+    record(
+        AuditEntry::new(0, 0, command, started)
+            .target(user)
+            .count(list.len()),
+    );
+
+    let risk = config
+        .risk
+        .enabled()
+        .then(|| config.risk.assess(&group_ids(&list)));
+    Ok(CommonGroups {
+        user,
+        username: pool.get_username(user).await,
+        count: list.len(),
+        groups: list,
+        missed: lookup.missed.len(),
+        risk,
+        ban: BanList::read().get(user).cloned(),
+    })
+}
+
+/// Compares `token` with `key` in constant time, by checking their HMACs keyed with `key`.
+fn token_matches(key: &str, token: &str) -> bool {
+    let mac = |s: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("any key length");
+        mac.update(s.as_bytes());
+        mac
+    };
+    mac(key)
+        .verify_slice(&mac(token).finalize().into_bytes())
+        .is_ok()
+}
+
+async fn handle(pool: &Pool, bot: &Client, req: Request) -> Response {
+    let config = MainConfig::read_config();
+    if !req
+        .bearer()
+        .is_some_and(|t| config.api.tokens.iter().any(|k| token_matches(k, t)))
+    {
+        return error(401, "invalid token");
+    }
+
+    let parts = req.path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match parts.as_slice() {
+        ["v1", "users", target, "common-groups"] if req.method == "GET" => {
+            match lookup(pool, bot, &config, target, "api").await {
+                Ok(result) => Response::json(200, &result),
+                Err((status, e)) => error(status, e),
+            }
+        }
+        ["v1", "users", _, "common-groups"] => error(405, "method not allowed"),
+        _ => error(404, "not found"),
+    }
+}
+
+pub async fn api_task(addr: String, pool: Pool, bot: Client) -> Result<()> {
+    serve("api", addr, move |req| {
+        let (pool, bot) = (pool.clone(), bot.clone());
+        async move { handle(&pool, &bot, req).await }
+    })
+    .await
+}
@@ -0,0 +1,194 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    time::Instant,
+};
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    defs::{AUDIT_FILE, AUDIT_KEEP, AUDIT_MAX_SIZE},
+    webhook::emit,
+};
+
+/// Audited commands that change the configuration or ban list, sent as `config` webhook events.
+const CONFIG_COMMANDS: &[&str] = &[
+    "/addadmin",
+    "/deladmin",
+    "/addgroup",
+    "/delgroup",
+    "/setlevel",
+    "/setscope",
+    "/watch",
+    "/unwatch",
+    "/gban",
+    "/ungban",
+    "/addtenant",
+    "/deltenant",
+    "/grant",
+    "/revoke",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    pub requester: i64,
+    pub chat: i64,
+    pub command: String,
+    pub target: Option<String>,
+    pub count: Option<usize>,
+    pub elapsed_ms: u64,
+}
+
+impl AuditEntry {
+    pub fn new(requester: i64, chat: i64, command: &str, started: Instant) -> Self {
+        Self {
+            time: Utc::now(),
+            requester,
+            chat,
+            command: command.to_string(),
+            target: None,
+            count: None,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    pub fn format(&self) -> String {
+        format!(
+            "[{}] {} @ {} {} {} -> {} ({}ms)",
+            self.time.format("%Y-%m-%d %H:%M:%S"),
+            self.requester,
+            self.chat,
+            self.command,
+            self.target.as_deref().unwrap_or("-"),
+            self.count
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            self.elapsed_ms
+        )
+    }
+}
+
+fn rotated(n: usize) -> String {
+    format!("{}.{}", AUDIT_FILE, n)
+}
+
+fn rotate() -> std::io::Result<()> {
+    for n in (1..AUDIT_KEEP).rev() {
+        if Path::new(&rotated(n)).exists() {
+            fs::rename(rotated(n), rotated(n + 1))?;
+        }
+    }
+    fs::rename(AUDIT_FILE, rotated(1))
+}
+
+/// Appends one JSON line, rotating `audit.log` to `audit.log.1..N` once it grows too large.
+pub fn record(entry: AuditEntry) {
+    let write = || -> anyhow::Result<()> {
+        if fs::metadata(AUDIT_FILE).is_ok_and(|m| m.len() > AUDIT_MAX_SIZE) {
+            rotate()?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(AUDIT_FILE)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    };
+
+    if let Err(e) = write() {
+        log::error!("write audit log failed: {}", e);
+    }
+    let event = if CONFIG_COMMANDS.contains(&entry.command.as_str()) {
+        "config"
+    } else {
+        "audit"
+    };
+    emit(event, entry);
+}
+
+/// Accepts `YYYY-MM-DD` or a relative `7d` / `12h` / `30m`; `None` for anything else,
+/// including spans too long to subtract from now.
+pub fn parse_since(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Some(d.and_hms_opt(0, 0, 0)?.and_utc());
+    }
+    let (split, _) = s.char_indices().last()?;
+    let (num, unit) = s.split_at(split);
+    let num = num.parse::<i64>().ok()?;
+    let duration = match unit {
+        "d" => TimeDelta::try_days(num)?,
+        "h" => TimeDelta::try_hours(num)?,
+        "m" => TimeDelta::try_minutes(num)?,
+        _ => return None,
+    };
+
+    Utc::now().checked_sub_signed(duration)
+}
+
+/// Entries by or about `user` since `since`, oldest first, across rotated files.
+pub fn search(user: Option<i64>, since: Option<DateTime<Utc>>) -> Vec<AuditEntry> {
+    let mut files = (1..=AUDIT_KEEP).rev().map(rotated).collect::<Vec<_>>();
+    files.push(AUDIT_FILE.to_string());
+
+    files
+        .iter()
+        .filter_map(|f| fs::read_to_string(f).ok())
+        .flat_map(|s| {
+            s.lines()
+                .filter_map(|l| serde_json::from_str::<AuditEntry>(l).ok())
+                .collect::<Vec<_>>()
+        })
+        .filter(|e| {
+            user.is_none_or(|u| {
+                e.requester == u || e.target.as_deref() == Some(u.to_string().as_str())
+            })
+        })
+        .filter(|e| since.is_none_or(|t| e.time >= t))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_since_accepts_dates_and_spans() {
+        assert_eq!(
+            parse_since("2024-03-01").unwrap().to_rfc3339(),
+            "2024-03-01T00:00:00+00:00"
+        );
+        let hours = Utc::now() - parse_since("12h").unwrap();
+        assert!((hours - TimeDelta::hours(12)).num_seconds().abs() < 5);
+        assert!(parse_since("7d").unwrap() < parse_since("30m").unwrap());
+    }
+
+    #[test]
+    fn parse_since_rejects_bad_input() {
+        for s in [
+            "",
+            "d",
+            "7",
+            "7w",
+            "-",
+            "2024-13-01",
+            "天7",
+            "1000000000d",
+            "9223372036854775807m",
+        ] {
+            assert!(parse_since(s).is_none(), "{}", s);
+        }
+    }
+}
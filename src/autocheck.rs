@@ -0,0 +1,75 @@
+use anyhow::Result;
+use grammers_client::{Client, InputMessage, types::Chat};
+
+use crate::{
+    check::format_result,
+    config::{AutoCheckNotify, MainConfig},
+    history::track,
+    pool::Pool,
+    watch::notify_admins,
+};
+
+/// Checks members who just joined `chat` and reports those in at least
+/// `auto_check.threshold` of the monitored groups in `config`, which is the tenant's
+/// view in a tenant's chat, so its groups are checked and its admins notified.
+pub async fn auto_check(
+    pool: Pool,
+    bot: Client,
+    chat: Chat,
+    users: Vec<i64>,
+    reply_id: i32,
+    config: MainConfig,
+    tracked: bool,
+) -> Result<()> {
+    for user in users {
+        if pool.is_self(&bot, user).await? {
+            continue;
+        }
+        let lookup = match pool.common_groups(user, &config.groups).await {
+            Ok(l) => l,
+            Err(e) => {
+                log::warn!("auto check: check {} failed: {}", user, e);
+                continue;
+            }
+        };
+        if tracked {
+            track(user, &lookup);
+        }
+        let list = lookup.groups;
+        if list.len() < config.auto_check.threshold {
+            continue;
+        }
+
+        log::info!(
+            "auto check: {} joined {} and is in {} monitored groups",
+            user,
+            chat.id(),
+            list.len()
+        );
+        match config.auto_check.notify {
+            AutoCheckNotify::Group => {
+                let vis = config.visibility(0, chat.id(), false);
+                bot.send_message(
+                    chat.clone(),
+                    InputMessage::text(format!("新成员检查\n{}", format_result(user, &list, &vis)))
+                        .reply_to(Some(reply_id)),
+                )
+                .await?;
+            }
+            AutoCheckNotify::Admins => {
+                notify_admins(&bot, &config, |admin| {
+                    let vis = config.visibility(admin, admin, true);
+                    format!(
+                        "新成员检查: 群 {} ({})\n{}",
+                        chat.name(),
+                        chat.id(),
+                        format_result(user, &list, &vis)
+                    )
+                })
+                .await;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::{collections::HashSet, io::Cursor};
+
+use anyhow::Result;
+use grammers_client::{
+    Client, InputMessage,
+    types::{Downloadable, Media, Message},
+};
+
+use crate::{
+    check::check_target,
+    defs::{BATCH_MAX_FILE_SIZE, BATCH_PROGRESS_STEP},
+    history::track,
+    policy::Visibility,
+    pool::Pool,
+    utils::csv_field,
+};
+
+/// Splits an uploaded text/CSV file into targets, skipping anything that is
+/// neither a numeric id nor an `@username` (e.g. a header row).
+pub fn parse_targets(content: &str) -> Vec<String> {
+    content
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .map(|s| s.trim().trim_matches('"'))
+        .filter(|s| s.parse::<i64>().is_ok() || (s.starts_with("@") && s.len() > 1))
+        .map(|s| s.to_string())
+        .collect()
+}
+
+async fn download(bot: &Client, media: Media) -> Result<String> {
+    let mut data = Vec::new();
+    let mut download = bot.iter_download(&Downloadable::Media(media));
+    while let Some(chunk) = download.next().await? {
+        data.extend(chunk);
+    }
+
+    Ok(String::from_utf8_lossy(&data).to_string())
+}
+
+/// Checks every target in an uploaded file, reporting progress by editing `status`.
+/// Results are only recorded in the shared history when `tracked`.
+pub async fn run_batch(
+    pool: Pool,
+    bot: Client,
+    status: Message,
+    media: Media,
+    groups: HashSet<i64>,
+    vis: Visibility,
+    tracked: bool,
+) -> Result<usize> {
+    let (chat, status_id) = (status.chat(), status.id());
+    if let Media::Document(ref d) = media {
+        if d.size() > BATCH_MAX_FILE_SIZE {
+            bot.edit_message(chat, status_id, "文件过大").await?;
+            return Ok(0);
+        }
+    } else {
+        bot.edit_message(chat, status_id, "请上传文本或CSV文件")
+            .await?;
+        return Ok(0);
+    }
+
+    let targets = parse_targets(&download(&bot, media).await?);
+    if targets.is_empty() {
+        bot.edit_message(chat, status_id, "文件中没有可查询的目标")
+            .await?;
+        return Ok(0);
+    }
+
+    let total = targets.len();
+    let mut csv = String::from("target,user_id,count,groups,error\n");
+    for (done, target) in targets.iter().enumerate() {
+        let row = match check_target(&pool, &bot, target, &groups).await {
+            Ok((user, lookup)) => {
+                if tracked {
+                    track(user, &lookup);
+                }
+                let list = &lookup.groups;
+                format!(
+                    "{},{},{},{},{}\n",
+                    csv_field(target),
+                    user,
+                    list.len(),
+                    csv_field(
+                        &list
+                            .iter()
+                            .filter(|g| vis.can_see(g))
+                            .map(|g| g.title.as_str())
+                            .collect::<Vec<_>>()
+                            .join(";")
+                    ),
+                    csv_field(&lookup.incomplete_note().unwrap_or_default()),
+                )
+            }
+            Err(e) => format!("{},,,,{}\n", csv_field(target), csv_field(&e.to_string())),
+        };
+        csv.push_str(&row);
+
+        if (done + 1) % BATCH_PROGRESS_STEP == 0
+            && done + 1 < total
+            && let Err(e) = bot
+                .edit_message(
+                    chat.clone(),
+                    status_id,
+                    format!("批量查询中... {}/{}", done + 1, total),
+                )
+                .await
+        {
+            log::warn!("batch progress update failed: {}", e);
+        }
+    }
+
+    let size = csv.len();
+    let mut stream = Cursor::new(csv.into_bytes());
+    let uploaded = bot
+        .upload_stream(&mut stream, size, "check.csv".to_string())
+        .await?;
+    bot.send_message(
+        chat.clone(),
+        InputMessage::text(format!("批量查询完成, 共 {} 个目标", total))
+            .document(uploaded)
+            .mime_type("text/csv"),
+    )
+    .await?;
+    bot.edit_message(chat, status_id, format!("批量查询完成 {}/{}", total, total))
+        .await?;
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_targets_skips_headers_and_junk() {
+        let content = "user_id,username\n\"123\",@bob\n456; @ ;abc\r\n-100789\t@amy";
+        assert_eq!(
+            parse_targets(content),
+            vec!["123", "@bob", "456", "-100789", "@amy"]
+        );
+        assert!(parse_targets("").is_empty());
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, fs, sync::Mutex};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use grammers_client::{Client, InputMessage, types::Message};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::MainConfig, defs::HANDLED_FILE, utils::command_name};
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Id of the last command handled in each chat. The update state is only saved now and
+/// then, so after a crash catch-up replays commands that already ran; message ids only
+/// grow within a chat, which tells them apart.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Handled {
+    chats: HashMap<i64, i32>,
+}
+
+impl Handled {
+    fn read() -> Self {
+        match fs::read_to_string(HANDLED_FILE) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn write(&self) {
+        let write = || -> anyhow::Result<()> {
+            fs::write(HANDLED_FILE, serde_json::to_string(self)?)?;
+            Ok(())
+        };
+
+        if let Err(e) = write() {
+            log::error!("write handled commands failed: {}", e);
+        }
+    }
+}
+
+/// Whether `msg` is a command caught up from before `online` that was already handled.
+pub fn replayed(msg: &Message, online: DateTime<Utc>) -> bool {
+    msg.date() < online
+        && command_name(msg.text()).is_some()
+        && Handled::read()
+            .chats
+            .get(&msg.chat().id())
+            .is_some_and(|last| msg.id() <= *last)
+}
+
+/// Remembers `msg` as handled when it is a command and catch-up is on.
+pub fn mark_handled(msg: &Message) {
+    if command_name(msg.text()).is_none() || MainConfig::read_config().catch_up_max_age.is_none() {
+        return;
+    }
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut handled = Handled::read();
+    let last = handled.chats.entry(msg.chat().id()).or_default();
+    *last = msg.id().max(*last);
+    handled.write();
+}
+
+/// Replies to a command caught up from before `online` that is older than `catch_up_max_age`,
+/// returning whether it should be skipped; never skips while catch-up is off.
+pub async fn skip_stale(bot: &Client, msg: &Message, online: DateTime<Utc>) -> Result<bool> {
+    let date = msg.date();
+    if date >= online || command_name(msg.text()).is_none() {
+        return Ok(false);
+    }
+    // Without catch-up nothing is fetched from the offline period, so anything dated
+    // before `online` arrived while the bot was starting and is answered as usual.
+    let Some(max_age) = MainConfig::read_config().catch_up_max_age else {
+        return Ok(false);
+    };
+    let age = (Utc::now() - date).num_seconds().max(0) as u64;
+    if age <= max_age {
+        return Ok(false);
+    }
+
+    bot.send_message(
+        msg.chat(),
+        InputMessage::text(format!(
+            "此命令发送于 {}, 当时 Bot 处于离线状态且已超过 {} 秒, 未执行, 请重新发送",
+            date.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            max_age
+        ))
+        .reply_to(Some(msg.id())),
+    )
+    .await?;
+    Ok(true)
+}
@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use grammers_client::{
+    Client, grammers_tl_types as tl,
+    types::{Chat, PackedChat},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::MainConfig,
+    gban::BanList,
+    history::{Diff, format_diff},
+    policy::Visibility,
+    pool::{Lookup, Pool},
+    risk::RiskRules,
+    utils::get_common_chats,
+};
+
+pub const ACCESS_HASH_ERROR: &str = "不能获取用户access_hash，你能确保我见过吗";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommonGroup {
+    pub id: i64,
+    pub title: String,
+    pub username: Option<String>,
+}
+
+impl CommonGroup {
+    pub fn display_username(&self) -> String {
+        match &self.username {
+            Some(s) => format!("@{}", s),
+            None => "N/A".to_string(),
+        }
+    }
+}
+
+/// `(user id, matched groups)` for one target, or the user-facing error.
+pub type CheckRow = Result<(i64, Lookup)>;
+
+fn link_user_id(url: &str) -> Option<i64> {
+    url.strip_prefix("tg://user?id=")?.parse::<i64>().ok()
+}
+
+/// Rewrites text mentions (`MessageEntityMentionName`) and `tg://user?id=` links in
+/// a command into plain ids, so the result can be split into targets like any other.
+pub fn expand_mentions(text: &str, entities: Option<&Vec<tl::enums::MessageEntity>>) -> String {
+    let mut spans = Vec::new();
+    for e in entities.into_iter().flatten() {
+        match e {
+            tl::enums::MessageEntity::MentionName(m) => spans.push((m.offset, m.length, m.user_id)),
+            tl::enums::MessageEntity::TextUrl(u) => {
+                if let Some(id) = link_user_id(&u.url) {
+                    spans.push((u.offset, u.length, id));
+                }
+            }
+            _ => {}
+        }
+    }
+    spans.sort_by_key(|(offset, _, _)| std::cmp::Reverse(*offset));
+
+    // Entity offsets are counted in UTF-16 code units.
+    let mut utf16 = text.encode_utf16().collect::<Vec<_>>();
+    for (offset, length, id) in spans {
+        let start = (offset as usize).min(utf16.len());
+        let end = (start + length as usize).min(utf16.len());
+        utf16.splice(start..end, format!(" {} ", id).encode_utf16());
+    }
+
+    String::from_utf16_lossy(&utf16)
+        .split_whitespace()
+        .map(|s| match link_user_id(s) {
+            Some(id) => id.to_string(),
+            None => s.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub async fn get_username(client: &Client, user: &PackedChat) -> Option<String> {
+    let resp = client
+        .invoke(&tl::functions::users::GetUsers {
+            id: vec![tl::enums::InputUser::User(tl::types::InputUser {
+                user_id: user.id,
+                access_hash: user.access_hash.unwrap_or_default(),
+            })],
+        })
+        .await
+        .ok()?;
+
+    match resp.into_iter().next() {
+        Some(tl::enums::User::User(u)) => u.username,
+        _ => None,
+    }
+}
+
+pub async fn common_groups(
+    client: &Client,
+    user: &PackedChat,
+    groups: &HashSet<i64>,
+) -> Result<Vec<CommonGroup>> {
+    let mut list = Vec::new();
+
+    for i in get_common_chats(client, user, 100).await? {
+        let id = i.id();
+        if !groups.contains(&id) {
+            continue;
+        }
+        let (title, username) = match i {
+            tl::enums::Chat::Channel(c) => (c.title, c.username),
+            tl::enums::Chat::Chat(c) => (c.title, None),
+            _ => (String::new(), None),
+        };
+        list.push(CommonGroup {
+            id,
+            title,
+            username,
+        });
+    }
+
+    Ok(list)
+}
+
+/// Reports whether a channel that posted on its own behalf is itself one of the monitored
+/// groups, and whether its linked discussion group is. Channels don't join groups, so
+/// this doesn't tell where else the channel posts.
+pub async fn channel_presence(
+    bot: &Client,
+    channel: &Chat,
+    groups: &HashSet<i64>,
+) -> Result<String> {
+    let input = match channel.pack().try_to_input_channel() {
+        Some(c) => c,
+        None => return Err(anyhow::anyhow!("发送者不是频道")),
+    };
+    let tl::enums::messages::ChatFull::Full(full) = bot
+        .invoke(&tl::functions::channels::GetFullChannel { channel: input })
+        .await?;
+    let linked = match full.full_chat {
+        tl::enums::ChatFull::ChannelFull(c) => c.linked_chat_id,
+        tl::enums::ChatFull::Full(_) => None,
+    };
+
+    let monitored = |id: i64| {
+        if groups.contains(&id) {
+            "是监控群"
+        } else {
+            "不是监控群"
+        }
+    };
+    let mut lines = vec![format!(
+        "频道 {} ({}) 本身{}",
+        channel.name(),
+        channel.id(),
+        monitored(channel.id())
+    )];
+    match linked {
+        Some(id) => lines.push(format!("其关联讨论群 {} {}", id, monitored(id))),
+        None => lines.push("该频道没有关联讨论群".to_string()),
+    }
+
+    Ok(lines.join("\n"))
+}
+
+pub fn group_ids(list: &[CommonGroup]) -> Vec<i64> {
+    list.iter().map(|g| g.id).collect()
+}
+
+pub fn format_result(user_id: i64, list: &[CommonGroup], vis: &Visibility) -> String {
+    if list.is_empty() {
+        return "未查询到共同群".to_string();
+    }
+    let visible = list.iter().filter(|g| vis.can_see(g)).collect::<Vec<_>>();
+    if visible.is_empty() {
+        return format!("与用户 {} 共同群 {} 个", user_id, list.len());
+    }
+
+    let mut s: String = visible
+        .iter()
+        .map(|g| vis.describe(g))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if visible.len() < list.len() {
+        s.push_str(&format!("\n另有 {} 个群不可见", list.len() - visible.len()));
+    }
+
+    format!(
+        r#"与用户 {} 共同群 {} 个
+具体群组:
+{}"#,
+        user_id,
+        list.len(),
+        s
+    )
+}
+
+/// `format_result` followed by a note when some groups couldn't be asked about, the
+/// user's global ban, risk assessment and the groups changed since the previous check.
+/// The ban list belongs to the global admins, so it is left out unless `global`.
+pub fn decorate_result(
+    user: i64,
+    lookup: &Lookup,
+    vis: &Visibility,
+    config: &MainConfig,
+    diff: Option<&Diff>,
+    global: bool,
+) -> String {
+    let list = &lookup.groups;
+    let mut result = format_result(user, list, vis);
+    if let Some(note) = lookup.incomplete_note() {
+        result = format!("{}\n{}", result, note);
+    }
+    if global && let Some(b) = BanList::read().get(user) {
+        result = format!("{}\n{}", result, b.format());
+    }
+    if config.risk.enabled() {
+        let a = config.risk.assess(&group_ids(list));
+        result = format!("{}\n{}", result, a.format());
+    }
+    if let Some(diff) = diff.and_then(|d| format_diff(d, vis)) {
+        result = format!("{}\n\n{}", result, diff);
+    }
+
+    result
+}
+
+/// One line per target: `target | id | count [| groups]`, failures carry the error text.
+pub fn format_table(rows: &[(String, CheckRow)], vis: &Visibility, risk: &RiskRules) -> String {
+    let mut lines = vec!["目标 | id | 共同群".to_string()];
+
+    for (target, row) in rows {
+        let mut line = match row {
+            Ok((user, Lookup { groups: list, .. })) if list.iter().any(|g| vis.can_see(g)) => {
+                format!(
+                    "{} | {} | {} | {}",
+                    target,
+                    user,
+                    list.len(),
+                    list.iter()
+                        .filter(|g| vis.can_see(g))
+                        .map(|g| g.title.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            }
+            Ok((user, Lookup { groups: list, .. })) => {
+                format!("{} | {} | {}", target, user, list.len())
+            }
+            Err(e) => format!("{} | - | {}", target, e),
+        };
+        if let Ok((_, lookup)) = row
+            && !lookup.missed.is_empty()
+        {
+            line.push_str(&format!(" ({} 个群未能查询)", lookup.missed.len()));
+        }
+        if let Ok((_, lookup)) = row
+            && risk.enabled()
+        {
+            let a = risk.assess(&group_ids(&lookup.groups));
+            line.push_str(&format!(
+                " | {:.1}{}",
+                a.score,
+                a.verdict.map(|v| format!(" {}", v)).unwrap_or_default()
+            ));
+        }
+        lines.push(line);
+    }
+
+    lines.join("\n")
+}
+
+/// Runs the whole `/check` path for one target string.
+pub async fn check_target(
+    pool: &Pool,
+    bot: &Client,
+    target: &str,
+    groups: &HashSet<i64>,
+) -> CheckRow {
+    let user = pool.resolve(target).await?;
+    if pool.is_self(bot, user).await? {
+        return Err(anyhow::anyhow!("不能查询自身"));
+    }
+    let lookup = pool.common_groups(user, groups).await?;
+
+    Ok((user, lookup))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mention(offset: i32, length: i32, user_id: i64) -> tl::enums::MessageEntity {
+        tl::types::MessageEntityMentionName {
+            offset,
+            length,
+            user_id,
+        }
+        .into()
+    }
+
+    #[test]
+    fn expand_mentions_uses_utf16_offsets() {
+        // "😀" takes two UTF-16 units, so the mention of "张三" starts at 10, not 9.
+        let text = "/check 😀 张三 @bob";
+        let entities = vec![mention(10, 2, 42)];
+        assert_eq!(expand_mentions(text, Some(&entities)), "/check 😀 42 @bob");
+    }
+
+    #[test]
+    fn expand_mentions_rewrites_links() {
+        let url = tl::types::MessageEntityTextUrl {
+            offset: 7,
+            length: 3,
+            url: "tg://user?id=5".to_string(),
+        }
+        .into();
+        let entities = vec![url, mention(11, 3, 6)];
+        assert_eq!(
+            expand_mentions("/check bob amy tg://user?id=7", Some(&entities)),
+            "/check 5 6 7"
+        );
+        assert_eq!(expand_mentions("/check  1   2", None), "/check 1 2");
+    }
+}
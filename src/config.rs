@@ -1,16 +1,81 @@
-use std::{collections::HashSet, fs, path::Path};
+use std::{collections::HashSet, fs, path::Path, sync::Mutex};
 
 use serde::{Deserialize, Serialize};
 
-use crate::defs::CONFIG_FILE;
+use crate::{
+    api::ApiConfig,
+    defs::CONFIG_FILE,
+    panel::PanelConfig,
+    policy::{AdminScope, ChatLevel},
+    risk::RiskRules,
+    roles::{Capabilities, RoleGrant},
+    tenant::Tenant,
+    webhook::Webhook,
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The last config that parsed, served while the file is invalid.
+static LAST_GOOD: Mutex<Option<MainConfig>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoCheckNotify {
+    #[default]
+    Group,
+    Admins,
+}
+
+/// Checks members joining a monitored group; `threshold = 0` disables it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AutoCheck {
+    pub threshold: usize,
+    pub notify: AutoCheckNotify,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MainConfig {
     pub groups: HashSet<i64>,
     pub admins: HashSet<i64>,
+    /// Session files of extra userbot accounts, besides `userbot.session`.
+    #[serde(default)]
+    pub userbots: Vec<String>,
+    /// Listen address of the Prometheus `/metrics` endpoint, with the `metrics` feature.
+    pub metrics_addr: Option<String>,
+    /// Path of the Unix socket for local JSON-RPC control; off when unset.
+    pub control_socket: Option<String>,
+    /// Bot token used when `bot.session` isn't signed in yet; `CHECKBOT_BOT_TOKEN` takes precedence.
+    pub bot_token: Option<String>,
+    /// Catch up on messages sent while the bot was offline; commands older than this many
+    /// seconds get an offline notice instead of an answer. Off when unset.
+    pub catch_up_max_age: Option<u64>,
+    #[serde(default)]
+    pub watchlist: HashSet<i64>,
+    #[serde(default)]
+    pub auto_check: AutoCheck,
+    #[serde(default)]
+    pub risk: RiskRules,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub panel: PanelConfig,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    #[serde(default)]
+    pub chat_levels: Vec<ChatLevel>,
+    #[serde(default)]
+    pub admin_scopes: Vec<AdminScope>,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub tenants: Vec<Tenant>,
+    #[serde(default)]
+    pub roles: Vec<RoleGrant>,
 }
 
 impl MainConfig {
+    pub fn is_admin(&self, id: i64) -> bool {
+        self.role(id).is_some()
+    }
+
     pub fn init() {
         let config = Path::new(CONFIG_FILE);
 
@@ -19,35 +84,38 @@ impl MainConfig {
         }
     }
 
+    /// Writes through a temporary file, so readers never see a half-written config.
     pub fn rewrite_config(c: Option<Self>) {
-        let default = match c {
-            Some(c) => c,
-            None => Self {
-                groups: HashSet::new(),
-                admins: HashSet::new(),
-            },
-        };
-        let config = Path::new(CONFIG_FILE);
+        let default = c.unwrap_or_default();
+        let tmp = format!("{}.tmp", CONFIG_FILE);
         let s = toml::to_string(&default).unwrap();
 
-        fs::write(config, s).unwrap();
+        fs::write(&tmp, s).unwrap();
+        fs::rename(&tmp, CONFIG_FILE).unwrap();
+    }
+
+    /// Parses the config file without replacing it when it's invalid.
+    pub fn try_read() -> anyhow::Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(CONFIG_FILE)?)?)
     }
 
+    /// Reads the config file, falling back to the last config that parsed while the
+    /// file is invalid, so a typo never replaces it with defaults.
     pub fn read_config() -> Self {
-        let config = Path::new(CONFIG_FILE);
-        let file = fs::read_to_string(config).unwrap();
-
-        let toml: Self = match toml::from_str(file.as_str()) {
-            Ok(s) => s,
-            Err(_) => {
-                Self::rewrite_config(None);
-                Self::read_config();
-                let file = fs::read_to_string(config).unwrap();
-                let toml: Self = toml::from_str(file.as_str()).unwrap();
-                toml
+        let mut last = LAST_GOOD.lock().unwrap_or_else(|e| e.into_inner());
+        match Self::try_read() {
+            Ok(config) => {
+                *last = Some(config.clone());
+                config
             }
-        };
-
-        toml
+            Err(e) => {
+                log::error!(
+                    "{} is invalid, keeping the last good config: {}",
+                    CONFIG_FILE,
+                    e
+                );
+                last.clone().unwrap_or_default()
+            }
+        }
     }
 }
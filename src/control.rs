@@ -0,0 +1,282 @@
+use std::{
+    fs,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::Path,
+    time::Instant,
+};
+
+use anyhow::{Result, bail};
+use grammers_client::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::{
+    api::lookup,
+    audit::{AuditEntry, record},
+    config::MainConfig,
+    gban::BanList,
+    health::snapshot,
+    pool::Pool,
+    status::format_status,
+};
+
+/// A JSON-RPC 2.0 request, one per line.
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+type RpcResult = std::result::Result<Value, RpcError>;
+
+fn rpc_error(code: i64, message: impl ToString) -> RpcError {
+    RpcError {
+        code,
+        message: message.to_string(),
+    }
+}
+
+fn param<T: for<'de> Deserialize<'de>>(
+    params: &Value,
+    name: &str,
+) -> std::result::Result<T, RpcError> {
+    params
+        .get(name)
+        .cloned()
+        .ok_or_else(|| rpc_error(INVALID_PARAMS, format!("missing param: {}", name)))
+        .and_then(|v| serde_json::from_value(v).map_err(|e| rpc_error(INVALID_PARAMS, e)))
+}
+
+fn to_value<T: Serialize>(value: T) -> RpcResult {
+    serde_json::to_value(value).map_err(|e| rpc_error(SERVER_ERROR, e))
+}
+
+/// Re-reads the config file, reporting errors instead of replacing it, and refreshes group membership.
+async fn reload(pool: &Pool) -> RpcResult {
+    let config = MainConfig::try_read().map_err(|e| rpc_error(SERVER_ERROR, e))?;
+    pool.refresh_coverage(&config.all_groups()).await;
+
+    to_value(serde_json::json!({
+        "groups": config.groups.len(),
+        "admins": config.admins.len(),
+        "uncovered": pool.uncovered(&config.all_groups()),
+    }))
+}
+
+fn set_group(params: &Value, add: bool) -> RpcResult {
+    let id = param::<i64>(params, "id")?;
+    let started = Instant::now();
+    let mut config = MainConfig::try_read().map_err(|e| rpc_error(SERVER_ERROR, e))?;
+    let changed = if add {
+        config.groups.insert(id)
+    } else {
+        config.groups.remove(&id)
+    };
+    if changed {
+        MainConfig::rewrite_config(Some(config));
+        let command = if add { "/addgroup" } else { "/delgroup" };
+        record(AuditEntry::new(0, 0, command, started).target(id));
+    }
+
+    to_value(serde_json::json!({ "changed": changed }))
+}
+
+fn dump(pool: &Pool) -> RpcResult {
+    let config = MainConfig::read_config();
+    let accounts = pool
+        .accounts()
+        .iter()
+        .map(|a| {
+            serde_json::json!({
+                "session": a.session,
+                "groups": a.coverage().map(|c| c.len()),
+                "cooldown_secs": a.cooldown_left().map(|d| d.as_secs()),
+                "cache": a.cache_stats(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let health = snapshot()
+        .into_iter()
+        .map(|(session, h)| {
+            serde_json::json!({
+                "session": session,
+                "healthy": h.healthy(),
+                "failures": h.failures,
+                "reconnects": h.reconnects,
+                "rtt_ms": h.rtt.map(|d| d.as_millis() as u64),
+                "last_ok_secs": h.last_ok.map(|t| t.elapsed().as_secs()),
+                "last_error": h.last_error,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    to_value(serde_json::json!({
+        "status": format_status(pool, &config),
+        "groups": config.groups,
+        "admins": config.admins,
+        "watchlist": config.watchlist,
+        "bans": BanList::read().bans.len(),
+        "accounts": accounts,
+        "health": health,
+    }))
+}
+
+async fn call(pool: &Pool, bot: &Client, req: &RpcRequest) -> RpcResult {
+    match req.method.as_str() {
+        "reload" => reload(pool).await,
+        "add_group" => set_group(&req.params, true),
+        "remove_group" => set_group(&req.params, false),
+        "lookup" => {
+            let target = param::<String>(&req.params, "target")?;
+            let config = MainConfig::read_config();
+            match lookup(pool, bot, &config, &target, "control").await {
+                Ok(result) => to_value(result),
+                // Lookup failures keep their HTTP status as the error code, as the REST API reports them.
+                Err((status, e)) => Err(rpc_error(status as i64, e)),
+            }
+        }
+        "dump" => dump(pool),
+        _ => Err(rpc_error(METHOD_NOT_FOUND, "method not found")),
+    }
+}
+
+async fn handle(pool: Pool, bot: Client, stream: UnixStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, result) = match serde_json::from_str::<RpcRequest>(&line) {
+            Ok(req) => {
+                log::info!("control: {}", req.method);
+                (req.id.clone(), call(&pool, &bot, &req).await)
+            }
+            Err(e) => (Value::Null, Err(rpc_error(PARSE_ERROR, e))),
+        };
+        let (result, error) = match result {
+            Ok(v) => (Some(v), None),
+            Err(e) => (None, Some(e)),
+        };
+        let mut out = serde_json::to_vec(&RpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        })?;
+        out.push(b'\n');
+        writer.write_all(&out).await?;
+    }
+
+    Ok(())
+}
+
+/// Removes a socket left behind by a previous run, refusing to touch anything else
+/// at `path` or a socket another instance is still listening on.
+fn remove_stale(path: &str) -> Result<()> {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !meta.file_type().is_socket() {
+        bail!("{} exists and is not a socket", path);
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("{} is in use by another process", path);
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Binds inside a private directory and moves the socket into place once it is
+/// `0600`, so no other user can connect before the permissions are set.
+fn bind(path: &str) -> Result<UnixListener> {
+    let dir = format!("{}.tmp", path);
+    let tmp = Path::new(&dir).join("control.sock");
+    // Left over from a crash between bind and rename; `remove_dir` fails unless it's empty.
+    let _ = fs::remove_file(&tmp);
+    let _ = fs::remove_dir(&dir);
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let bound = UnixListener::bind(&tmp).and_then(|listener| {
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&tmp, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&tmp);
+    fs::remove_dir(&dir)?;
+
+    Ok(bound?)
+}
+
+/// Serves newline-delimited JSON-RPC on a Unix socket only the bot's own user can open.
+pub async fn control_task(path: String, pool: Pool, bot: Client) -> Result<()> {
+    remove_stale(&path)?;
+    let listener = bind(&path)?;
+    log::info!("control socket listening on {}", path);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let (pool, bot) = (pool.clone(), bot.clone());
+        tokio::spawn(async move {
+            if let Err(e) = handle(pool, bot, stream).await {
+                log::warn!("control: connection failed: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bind_replaces_only_stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("control-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bot.sock").to_string_lossy().into_owned();
+
+        let listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(remove_stale(&path).is_err());
+
+        drop(listener);
+        remove_stale(&path).unwrap();
+        assert!(!Path::new(&path).exists());
+
+        fs::write(&path, "not a socket").unwrap();
+        assert!(remove_stale(&path).is_err());
+        assert!(Path::new(&path).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,5 +3,66 @@ pub const SUPER_ADMIN: i64 = 1;
 pub const API_HASH: &str = "1";
 pub const SESSION_FILE: &str = "userbot.session";
 pub const BOT_SESSION_FILE: &str = "bot.session";
+/// Telegram's default datacenter, where new sessions start out.
+pub const DEFAULT_DC: i32 = 2;
+pub const ENV_PREFIX: &str = "CHECKBOT";
+pub const LOGIN_WAIT: u64 = 300;
+pub const QR_POLL: u64 = 3;
+pub const SESSION_KDF_ROUNDS: u32 = 100_000;
+pub const SESSION_SAVE_INTERVAL: u64 = 300;
+pub const SHUTDOWN_TIMEOUT: u64 = 30;
 pub const CONFIG_FILE: &str = "config.toml";
 pub const HEART_TIME: u64 = 180;
+pub const HEART_TIMEOUT: u64 = 30;
+pub const HEART_MAX_FAILURES: u32 = 3;
+pub const RECONNECT_BASE_DELAY: u64 = 1;
+pub const RECONNECT_MAX_DELAY: u64 = 300;
+pub const BATCH_MAX_FILE_SIZE: i64 = 1024 * 1024;
+pub const BATCH_PROGRESS_STEP: usize = 10;
+/// Targets one inline `/check` may name; longer lists go through a batch file.
+pub const CHECK_MAX_TARGETS: usize = 20;
+/// Telegram's message length limit, in UTF-16 code units.
+pub const MESSAGE_MAX_LEN: usize = 4096;
+pub const AUDIT_FILE: &str = "audit.log";
+pub const AUDIT_MAX_SIZE: u64 = 5 * 1024 * 1024;
+pub const AUDIT_KEEP: usize = 5;
+pub const AUDIT_SHOW: usize = 20;
+pub const HISTORY_FILE: &str = "history.json";
+pub const HISTORY_KEEP: usize = 50;
+pub const HANDLED_FILE: &str = "handled.json";
+pub const WATCH_INTERVAL: u64 = 600;
+pub const BAN_FILE: &str = "bans.json";
+pub const COVERAGE_INTERVAL: u64 = 1800;
+pub const BANNED_COOLDOWN: u64 = 3600;
+pub const HTTP_MAX_REQUEST: usize = 64 * 1024;
+pub const HTTP_TIMEOUT: u64 = 10;
+pub const WEBHOOK_RETRIES: u32 = 5;
+pub const PANEL_LINK_TTL: u64 = 300;
+pub const PANEL_SESSION_TTL: u64 = 12 * 3600;
+pub const PANEL_SHOW: usize = 100;
+/// Commands the bot answers; anything else starting with `/` is left to other bots.
+pub const COMMANDS: &[&str] = &[
+    "/addadmin",
+    "/addgroup",
+    "/setlevel",
+    "/setscope",
+    "/audit",
+    "/history",
+    "/watch",
+    "/unwatch",
+    "/watchlist",
+    "/status",
+    "/panel",
+    "/gban",
+    "/ungban",
+    "/check",
+    "/reply",
+    "/addtenant",
+    "/deltenant",
+    "/tenant",
+    "/grant",
+    "/revoke",
+    "/roles",
+    "/delgroup",
+    "/deladmin",
+];
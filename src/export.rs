@@ -0,0 +1,147 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use grammers_client::{Client, InputMessage, types::Chat};
+use serde::Serialize;
+
+use crate::{check::CommonGroup, utils::csv_field};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportRow {
+    pub user_id: i64,
+    pub username: Option<String>,
+    pub group_id: Option<i64>,
+    pub group_title: Option<String>,
+    pub group_username: Option<String>,
+    pub fetched_at: String,
+}
+
+/// Strips `--format csv|json` (or `--format=...`) from the command arguments.
+pub fn parse_format(args: &str) -> Result<(Option<ExportFormat>, Vec<&str>)> {
+    let mut format = None;
+    let mut rest = Vec::new();
+    let mut iter = args.split_whitespace();
+
+    while let Some(arg) = iter.next() {
+        let value = if arg == "--format" {
+            iter.next().unwrap_or_default()
+        } else if let Some(v) = arg.strip_prefix("--format=") {
+            v
+        } else {
+            rest.push(arg);
+            continue;
+        };
+        format = Some(match value.to_lowercase().as_str() {
+            "csv" => ExportFormat::Csv,
+            "json" => ExportFormat::Json,
+            _ => return Err(anyhow::anyhow!("不支持的格式, 可选 csv 或 json")),
+        });
+    }
+
+    Ok((format, rest))
+}
+
+/// One row per matched group; a user without common groups still gets a row with empty group columns.
+pub fn to_rows(user_id: i64, username: Option<String>, list: &[CommonGroup]) -> Vec<ExportRow> {
+    let fetched_at = chrono::Utc::now().to_rfc3339();
+
+    if list.is_empty() {
+        return vec![ExportRow {
+            user_id,
+            username,
+            group_id: None,
+            group_title: None,
+            group_username: None,
+            fetched_at,
+        }];
+    }
+
+    list.iter()
+        .map(|g| ExportRow {
+            user_id,
+            username: username.clone(),
+            group_id: Some(g.id),
+            group_title: Some(g.title.clone()),
+            group_username: g.username.clone(),
+            fetched_at: fetched_at.clone(),
+        })
+        .collect()
+}
+
+pub fn render(rows: &[ExportRow], format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(rows)?),
+        ExportFormat::Csv => {
+            let mut csv =
+                String::from("user_id,username,group_id,group_title,group_username,fetched_at\n");
+            for r in rows {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{}\n",
+                    r.user_id,
+                    csv_field(r.username.as_deref().unwrap_or_default()),
+                    r.group_id.map(|i| i.to_string()).unwrap_or_default(),
+                    csv_field(r.group_title.as_deref().unwrap_or_default()),
+                    csv_field(r.group_username.as_deref().unwrap_or_default()),
+                    r.fetched_at
+                ));
+            }
+            Ok(csv)
+        }
+    }
+}
+
+pub async fn send_export(
+    bot: &Client,
+    chat: Chat,
+    reply_id: Option<i32>,
+    caption: &str,
+    rows: &[ExportRow],
+    format: ExportFormat,
+) -> Result<()> {
+    let (name, mime) = match format {
+        ExportFormat::Csv => ("common_groups.csv", "text/csv"),
+        ExportFormat::Json => ("common_groups.json", "application/json"),
+    };
+    let data = render(rows, format)?.into_bytes();
+    let size = data.len();
+    let mut stream = Cursor::new(data);
+    let uploaded = bot
+        .upload_stream(&mut stream, size, name.to_string())
+        .await?;
+    bot.send_message(
+        chat,
+        InputMessage::text(caption)
+            .document(uploaded)
+            .mime_type(mime)
+            .reply_to(reply_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_format_strips_the_flag() {
+        let (format, rest) = parse_format("123 --format json @bob").unwrap();
+        assert_eq!(format, Some(ExportFormat::Json));
+        assert_eq!(rest, vec!["123", "@bob"]);
+
+        let (format, rest) = parse_format("--format=CSV 123").unwrap();
+        assert_eq!(format, Some(ExportFormat::Csv));
+        assert_eq!(rest, vec!["123"]);
+
+        assert_eq!(parse_format("123").unwrap().0, None);
+        assert!(parse_format("123 --format xml").is_err());
+        assert!(parse_format("123 --format").is_err());
+    }
+}
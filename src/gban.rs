@@ -0,0 +1,257 @@
+use std::{collections::HashSet, fs};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use grammers_client::{
+    Client, InvocationError,
+    client::chats::ParticipantPermissions,
+    grammers_tl_types as tl,
+    types::{Chat, PackedChat},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    check::CommonGroup, defs::BAN_FILE, policy::Visibility, pool::Pool, utils::monitored_chats,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanEntry {
+    pub user: i64,
+    pub reason: Option<String>,
+    pub by: i64,
+    pub time: DateTime<Utc>,
+}
+
+impl BanEntry {
+    pub fn format(&self) -> String {
+        format!(
+            "已被全局封禁 ({}){}",
+            self.time.format("%Y-%m-%d"),
+            self.reason
+                .as_ref()
+                .map(|r| format!(": {}", r))
+                .unwrap_or_default()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BanList {
+    pub bans: Vec<BanEntry>,
+}
+
+impl BanList {
+    pub fn read() -> Self {
+        match fs::read_to_string(BAN_FILE) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn write(&self) {
+        let write = || -> anyhow::Result<()> {
+            fs::write(BAN_FILE, serde_json::to_string_pretty(self)?)?;
+            Ok(())
+        };
+
+        if let Err(e) = write() {
+            log::error!("write ban list failed: {}", e);
+        }
+    }
+
+    pub fn get(&self, user: i64) -> Option<&BanEntry> {
+        self.bans.iter().find(|b| b.user == user)
+    }
+
+    pub fn add(&mut self, entry: BanEntry) {
+        self.bans.retain(|b| b.user != entry.user);
+        self.bans.push(entry);
+    }
+
+    pub fn remove(&mut self, user: i64) -> bool {
+        let len = self.bans.len();
+        self.bans.retain(|b| b.user != user);
+        len != self.bans.len()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BanOutcome {
+    Done,
+    /// Dry run: the userbot has the rights to do it.
+    Possible,
+    NoPermission,
+    NotMember,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct BanReport {
+    pub group: i64,
+    pub title: Option<String>,
+    pub username: Option<String>,
+    pub outcome: BanOutcome,
+}
+
+fn can_ban(perms: &ParticipantPermissions) -> bool {
+    match perms {
+        ParticipantPermissions::Channel(tl::enums::ChannelParticipant::Admin(p)) => {
+            let tl::enums::ChatAdminRights::Rights(rights) = &p.admin_rights;
+            rights.ban_users
+        }
+        ParticipantPermissions::Chat(_) => perms.is_admin(),
+        _ => perms.is_creator(),
+    }
+}
+
+fn classify(e: InvocationError) -> BanOutcome {
+    if e.is("CHAT_ADMIN_REQUIRED") || e.is("RIGHT_FORBIDDEN") || e.is("USER_ADMIN_INVALID") {
+        BanOutcome::NoPermission
+    } else {
+        BanOutcome::Failed(e.to_string())
+    }
+}
+
+async fn apply_one(
+    client: &Client,
+    chat: &Chat,
+    user: PackedChat,
+    ban: bool,
+    dry_run: bool,
+) -> BanOutcome {
+    if dry_run {
+        let me = match client.get_me().await {
+            Ok(me) => me,
+            Err(e) => return BanOutcome::Failed(e.to_string()),
+        };
+        return match client.get_permissions(chat, &me).await {
+            Ok(p) if can_ban(&p) => BanOutcome::Possible,
+            Ok(_) => BanOutcome::NoPermission,
+            Err(e) => classify(e),
+        };
+    }
+
+    // Without any right taken away, `channels.EditBanned` lifts the ban again.
+    let result = if ban {
+        client
+            .set_banned_rights(chat, user)
+            .view_messages(false)
+            .await
+    } else {
+        client.set_banned_rights(chat, user).await
+    };
+
+    match result {
+        Ok(()) => BanOutcome::Done,
+        Err(e) => classify(e),
+    }
+}
+
+/// Bans (or unbans) `user` in every monitored group. Each group is handled by
+/// the first userbot that is a member of it and has the rights to do so.
+pub async fn apply(
+    pool: &Pool,
+    user: i64,
+    groups: &HashSet<i64>,
+    ban: bool,
+    dry_run: bool,
+) -> Result<Vec<BanReport>> {
+    let mut reports: Vec<BanReport> = Vec::new();
+    let settled = |reports: &[BanReport], group: i64| {
+        reports.iter().any(|r| {
+            r.group == group && matches!(r.outcome, BanOutcome::Done | BanOutcome::Possible)
+        })
+    };
+
+    for account in pool.accounts() {
+        let chats = monitored_chats(&account.client, groups)
+            .await?
+            .into_iter()
+            .filter(|c| !settled(&reports, c.id()))
+            .collect::<Vec<_>>();
+        if chats.is_empty() {
+            continue;
+        }
+        let packed = match account.packed_user(user).await {
+            Ok(p) => p,
+            Err(_) => {
+                log::warn!(
+                    "gban: {} can't get access_hash of {}",
+                    account.session,
+                    user
+                );
+                continue;
+            }
+        };
+        for chat in &chats {
+            let outcome = apply_one(&account.client, chat, packed, ban, dry_run).await;
+            reports.retain(|r| r.group != chat.id());
+            reports.push(BanReport {
+                group: chat.id(),
+                title: Some(chat.name().to_string()),
+                username: chat.username().map(|u| u.to_string()),
+                outcome,
+            });
+        }
+    }
+    for group in groups {
+        if !reports.iter().any(|r| r.group == *group) {
+            reports.push(BanReport {
+                group: *group,
+                title: None,
+                username: None,
+                outcome: BanOutcome::NotMember,
+            });
+        }
+    }
+
+    Ok(reports)
+}
+
+/// Names only the groups `vis` may see; the rest of each section is given as a count.
+pub fn format_reports(reports: &[BanReport], vis: &Visibility) -> String {
+    let section = |label: &str, filter: &dyn Fn(&BanOutcome) -> bool| {
+        let matched = reports
+            .iter()
+            .filter(|r| filter(&r.outcome))
+            .collect::<Vec<_>>();
+        if matched.is_empty() {
+            return None;
+        }
+        let named = matched
+            .iter()
+            .filter_map(|r| {
+                let group = CommonGroup {
+                    id: r.group,
+                    title: r.title.clone()?,
+                    username: r.username.clone(),
+                };
+                if !vis.can_see(&group) {
+                    return None;
+                }
+                Some(match &r.outcome {
+                    BanOutcome::Failed(e) => format!("{} ({})", group.title, e),
+                    _ => group.title,
+                })
+            })
+            .collect::<Vec<_>>();
+        let hidden = matched.len() - named.len();
+        Some(match (named.is_empty(), hidden) {
+            (true, _) => format!("{}: {} 个群", label, hidden),
+            (false, 0) => format!("{}: {}", label, named.join(", ")),
+            (false, _) => format!("{}: {} 及其他 {} 个群", label, named.join(", "), hidden),
+        })
+    };
+
+    [
+        section("成功", &|o| matches!(o, BanOutcome::Done)),
+        section("可执行", &|o| matches!(o, BanOutcome::Possible)),
+        section("缺少权限", &|o| matches!(o, BanOutcome::NoPermission)),
+        section("未加入", &|o| matches!(o, BanOutcome::NotMember)),
+        section("失败", &|o| matches!(o, BanOutcome::Failed(_))),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join("\n")
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeMap,
+    ops::ControlFlow,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use grammers_client::{Client, ReconnectionPolicy, Update, grammers_tl_types as tl};
+use tokio::{
+    sync::Notify,
+    time::{sleep, timeout},
+};
+
+use crate::{
+    defs::{
+        HEART_MAX_FAILURES, HEART_TIME, HEART_TIMEOUT, RECONNECT_BASE_DELAY, RECONNECT_MAX_DELAY,
+    },
+    login, metrics,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientHealth {
+    pub last_ok: Option<Instant>,
+    pub rtt: Option<Duration>,
+    /// Consecutive failed pings or update reads.
+    pub failures: u32,
+    pub reconnects: u32,
+    pub last_error: Option<String>,
+}
+
+impl ClientHealth {
+    pub fn healthy(&self) -> bool {
+        self.failures < HEART_MAX_FAILURES
+    }
+}
+
+static HEALTH: Mutex<BTreeMap<String, ClientHealth>> = Mutex::new(BTreeMap::new());
+static DEAD: Notify = Notify::const_new();
+
+fn update<F: FnOnce(&mut ClientHealth)>(session: &str, f: F) {
+    f(HEALTH
+        .lock()
+        .unwrap()
+        .entry(session.to_string())
+        .or_default());
+}
+
+pub fn record_ok(session: &str, rtt: Option<Duration>) {
+    update(session, |h| {
+        h.last_ok = Some(Instant::now());
+        h.rtt = rtt.or(h.rtt);
+        h.failures = 0;
+    });
+}
+
+pub fn record_err(session: &str, e: &dyn std::fmt::Display) {
+    update(session, |h| {
+        h.failures += 1;
+        h.last_error = Some(e.to_string());
+    });
+}
+
+pub fn snapshot() -> BTreeMap<String, ClientHealth> {
+    HEALTH.lock().unwrap().clone()
+}
+
+/// `RECONNECT_BASE_DELAY * 2^attempts`, capped at `RECONNECT_MAX_DELAY`.
+fn backoff(attempts: usize) -> Duration {
+    let secs = RECONNECT_BASE_DELAY.saturating_mul(1 << attempts.min(16));
+    Duration::from_secs(secs.min(RECONNECT_MAX_DELAY))
+}
+
+/// Reconnection policy of one session: retries forever with exponential
+/// backoff, so the client and its update state survive network outages.
+pub struct Reconnect {
+    session: String,
+}
+
+impl Reconnect {
+    pub fn leak(session: &str) -> &'static Self {
+        Box::leak(Box::new(Self {
+            session: session.to_string(),
+        }))
+    }
+}
+
+impl ReconnectionPolicy for Reconnect {
+    /// Asked with `attempts == 0` when the connection is lost, then after each failed attempt.
+    fn should_retry(&self, attempts: usize) -> ControlFlow<(), Duration> {
+        if attempts == 0 {
+            log::warn!("{}: connection lost, reconnecting", self.session);
+            update(&self.session, |h| h.reconnects += 1);
+            return ControlFlow::Continue(Duration::ZERO);
+        }
+
+        let delay = backoff(attempts);
+        log::warn!(
+            "{}: reconnect attempt {} failed, retrying in {:?}",
+            self.session,
+            attempts,
+            delay
+        );
+        ControlFlow::Continue(delay)
+    }
+}
+
+/// Next update of `client`; read errors are retried with backoff instead of ending the loop.
+pub async fn next_update(session: &str, client: &Client) -> Update {
+    let mut attempts = 0;
+    loop {
+        match client.next_update().await {
+            Ok(update) => {
+                if attempts > 0 {
+                    log::info!("{}: receiving updates again", session);
+                    record_ok(session, None);
+                }
+                return update;
+            }
+            Err(e) => {
+                log::error!("{}: get update failed: {}", session, e);
+                record_err(session, &e);
+                sleep(backoff(attempts)).await;
+                attempts += 1;
+            }
+        }
+    }
+}
+
+/// Resolves once a connection stopped answering while a new one to the same account works.
+pub async fn dead() {
+    DEAD.notified().await
+}
+
+/// Whether a fresh connection with `session` answers a ping.
+async fn probe(session: &str) -> bool {
+    let ping = async {
+        let client = login::connect(session, false).await?;
+        client.invoke(&tl::functions::Ping { ping_id: 0 }).await?;
+        anyhow::Ok(())
+    };
+    let result = timeout(Duration::from_secs(HEART_TIMEOUT), ping)
+        .await
+        .unwrap_or_else(|e| Err(e.into()));
+    if let Err(e) = &result {
+        log::warn!("{}: new connection failed: {}", session, e);
+    }
+
+    result.is_ok()
+}
+
+/// Pings every `HEART_TIME` seconds, and sooner with backoff while pings fail,
+/// which also drives the reconnection of a dead connection. A half-open connection
+/// is never noticed by the client itself, so once `HEART_MAX_FAILURES` pings failed
+/// and a new connection works, `dead` resolves for the clients to be rebuilt.
+pub async fn heart_task(session: String, client: Client) -> Result<()> {
+    log::info!(
+        "Heart task is running for {}",
+        client.get_me().await?.full_name()
+    );
+    record_ok(&session, None);
+    let mut failures = 0;
+    loop {
+        let delay = if failures == 0 {
+            Duration::from_secs(HEART_TIME)
+        } else {
+            backoff(failures)
+        };
+        sleep(delay).await;
+
+        let started = Instant::now();
+        match timeout(
+            Duration::from_secs(HEART_TIMEOUT),
+            client.invoke(&tl::functions::Ping { ping_id: 0 }),
+        )
+        .await
+        {
+            Ok(Ok(_)) => {
+                log::info!("Heart task: successful");
+                record_ok(&session, Some(started.elapsed()));
+                metrics::heartbeat(&session, true);
+                failures = 0;
+                continue;
+            }
+            Ok(Err(e)) => {
+                log::warn!("Heart task: error: {}", e);
+                record_err(&session, &e);
+            }
+            Err(e) => {
+                log::warn!("Heart task start failed: {}", e);
+                record_err(&session, &e);
+            }
+        }
+        metrics::heartbeat(&session, false);
+        failures += 1;
+        if failures >= HEART_MAX_FAILURES as usize && probe(&session).await {
+            log::error!("{}: connection considered dead, restarting", session);
+            DEAD.notify_one();
+        }
+    }
+}
+
+fn ago(t: Instant) -> String {
+    let secs = t.elapsed().as_secs();
+    match secs {
+        0..60 => format!("{}秒前", secs),
+        60..3600 => format!("{}分钟前", secs / 60),
+        _ => format!("{}小时前", secs / 3600),
+    }
+}
+
+pub fn format_health() -> String {
+    let health = snapshot();
+    if health.is_empty() {
+        return "暂无连接状态".to_string();
+    }
+
+    health
+        .iter()
+        .map(|(session, h)| {
+            let mut line = format!("{}: {}", session, if h.healthy() { "正常" } else { "异常" });
+            if let Some(t) = h.last_ok {
+                line.push_str(&format!(", 上次成功 {}", ago(t)));
+            }
+            if let Some(rtt) = h.rtt {
+                line.push_str(&format!(", 延迟 {}ms", rtt.as_millis()));
+            }
+            if h.reconnects > 0 {
+                line.push_str(&format!(", 重连 {} 次", h.reconnects));
+            }
+            if !h.healthy()
+                && let Some(e) = &h.last_error
+            {
+                line.push_str(&format!(", 错误: {}", e));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
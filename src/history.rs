@@ -0,0 +1,291 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    check::CommonGroup,
+    defs::{HISTORY_FILE, HISTORY_KEEP},
+    policy::Visibility,
+    pool::Lookup,
+    webhook::risk_alert,
+};
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// A set of matched groups that stayed the same between `first_seen` and `last_seen`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub groups: Vec<CommonGroup>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Diff {
+    pub joined: Vec<CommonGroup>,
+    pub left: Vec<CommonGroup>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct History {
+    pub targets: HashMap<i64, Vec<Snapshot>>,
+    /// Groups each watched user had when the watchlist last saw them, kept apart from
+    /// `targets` so a `/check` between polls can't use up a change before it is alerted.
+    #[serde(default)]
+    pub watched: HashMap<i64, Vec<CommonGroup>>,
+}
+
+impl History {
+    pub fn read() -> Self {
+        match fs::read_to_string(HISTORY_FILE) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn write(&self) {
+        let write = || -> anyhow::Result<()> {
+            fs::write(HISTORY_FILE, serde_json::to_string(self)?)?;
+            Ok(())
+        };
+
+        if let Err(e) = write() {
+            log::error!("write history failed: {}", e);
+        }
+    }
+
+    /// Stores the latest result for `user` and returns what changed since the previous check.
+    fn push(&mut self, user: i64, list: &[CommonGroup], unqueried: &HashSet<i64>) -> Option<Diff> {
+        let now = Utc::now();
+        let snapshots = self.targets.entry(user).or_default();
+        let list = &carry(
+            snapshots.last().map(|s| s.groups.as_slice()),
+            list,
+            unqueried,
+        );
+
+        let diff = match snapshots.last_mut() {
+            Some(last) => {
+                let diff = compare(&last.groups, list);
+                if diff.joined.is_empty() && diff.left.is_empty() {
+                    last.last_seen = now;
+                    last.groups = list.to_vec();
+                    return Some(diff);
+                }
+                Some(diff)
+            }
+            None => None,
+        };
+
+        snapshots.push(Snapshot {
+            first_seen: now,
+            last_seen: now,
+            groups: list.to_vec(),
+        });
+        if snapshots.len() > HISTORY_KEEP {
+            snapshots.drain(..snapshots.len() - HISTORY_KEEP);
+        }
+
+        diff
+    }
+}
+
+/// `list` plus the groups of `previous` among `unqueried`, so a group nobody could ask
+/// about keeps its last known state instead of reading as left.
+fn carry(
+    previous: Option<&[CommonGroup]>,
+    list: &[CommonGroup],
+    unqueried: &HashSet<i64>,
+) -> Vec<CommonGroup> {
+    let mut list = list.to_vec();
+    list.extend(
+        previous
+            .unwrap_or_default()
+            .iter()
+            .filter(|g| unqueried.contains(&g.id) && !list.iter().any(|l| l.id == g.id))
+            .cloned()
+            .collect::<Vec<_>>(),
+    );
+    list
+}
+
+fn compare(previous: &[CommonGroup], list: &[CommonGroup]) -> Diff {
+    let ids = |l: &[CommonGroup]| {
+        l.iter()
+            .map(|g| (g.id, g.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let (previous, current) = (ids(previous), ids(list));
+
+    Diff {
+        joined: current
+            .iter()
+            .filter(|(id, _)| !previous.contains_key(id))
+            .map(|(_, g)| g.clone())
+            .collect(),
+        left: previous
+            .iter()
+            .filter(|(id, _)| !current.contains_key(id))
+            .map(|(_, g)| g.clone())
+            .collect(),
+    }
+}
+
+/// Records a check result; `None` means this is the first time `user` was checked.
+/// Risk alerts are only sent when the matched groups change, not on every re-check.
+pub fn track(user: i64, lookup: &Lookup) -> Option<Diff> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut history = History::read();
+    let diff = history.push(user, &lookup.groups, &lookup.unqueried());
+    history.write();
+    if diff
+        .as_ref()
+        .is_none_or(|d| !d.joined.is_empty() || !d.left.is_empty())
+    {
+        risk_alert(user, &lookup.groups);
+    }
+
+    diff
+}
+
+/// Stores `list` as the watchlist's view of `user`, returning what changed since the
+/// previous alert; `None` when there was nothing to compare with.
+pub fn watch_diff(user: i64, lookup: &Lookup) -> Option<Diff> {
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut history = History::read();
+    let list = carry(
+        history.watched.get(&user).map(Vec::as_slice),
+        &lookup.groups,
+        &lookup.unqueried(),
+    );
+    let previous = history.watched.insert(user, list.clone());
+    history.write();
+
+    previous.map(|p| compare(&p, &list))
+}
+
+fn names(list: &[CommonGroup], vis: &Visibility) -> String {
+    let visible = list
+        .iter()
+        .filter(|g| vis.can_see(g))
+        .map(|g| g.title.as_str())
+        .collect::<Vec<_>>();
+    let hidden = list.len() - visible.len();
+
+    match (visible.is_empty(), hidden) {
+        (true, _) => format!("{} 个", list.len()),
+        (false, 0) => visible.join(", "),
+        (false, h) => format!("{} 等 (另有 {} 个不可见)", visible.join(", "), h),
+    }
+}
+
+pub fn format_diff(diff: &Diff, vis: &Visibility) -> Option<String> {
+    let mut lines = Vec::new();
+    if !diff.joined.is_empty() {
+        lines.push(format!("自上次查询新加入: {}", names(&diff.joined, vis)));
+    }
+    if !diff.left.is_empty() {
+        lines.push(format!("自上次查询已退出: {}", names(&diff.left, vis)));
+    }
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+pub fn format_timeline(user: i64, snapshots: &[Snapshot], vis: &Visibility) -> String {
+    let lines = snapshots
+        .iter()
+        .map(|s| {
+            format!(
+                "[{} ~ {}] 共同群 {} 个{}",
+                s.first_seen.format("%Y-%m-%d %H:%M"),
+                s.last_seen.format("%Y-%m-%d %H:%M"),
+                s.groups.len(),
+                if s.groups.iter().any(|g| vis.can_see(g)) {
+                    format!(": {}", names(&s.groups, vis))
+                } else {
+                    String::new()
+                }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("用户 {} 查询历史:\n{}", user, lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(id: i64) -> CommonGroup {
+        CommonGroup {
+            id,
+            title: format!("group {}", id),
+            username: None,
+        }
+    }
+
+    fn ids(list: &[CommonGroup]) -> Vec<i64> {
+        list.iter().map(|g| g.id).collect()
+    }
+
+    #[test]
+    fn compare_splits_joined_and_left() {
+        let diff = compare(&[group(1), group(2)], &[group(2), group(3)]);
+        assert_eq!(ids(&diff.joined), vec![3]);
+        assert_eq!(ids(&diff.left), vec![1]);
+
+        let same = compare(&[group(1)], &[group(1)]);
+        assert!(same.joined.is_empty() && same.left.is_empty());
+    }
+
+    #[test]
+    fn push_merges_unchanged_results() {
+        let mut history = History::default();
+        let none = HashSet::new();
+        assert!(history.push(7, &[group(1)], &none).is_none());
+
+        let diff = history.push(7, &[group(1)], &none).unwrap();
+        assert!(diff.joined.is_empty() && diff.left.is_empty());
+        assert_eq!(history.targets[&7].len(), 1);
+
+        let diff = history.push(7, &[group(2)], &none).unwrap();
+        assert_eq!((ids(&diff.joined), ids(&diff.left)), (vec![2], vec![1]));
+        assert_eq!(history.targets[&7].len(), 2);
+    }
+
+    #[test]
+    fn push_keeps_the_latest_snapshots() {
+        let mut history = History::default();
+        for id in 0..HISTORY_KEEP as i64 + 3 {
+            history.push(7, &[group(id)], &HashSet::new());
+        }
+        let snapshots = &history.targets[&7];
+        assert_eq!(snapshots.len(), HISTORY_KEEP);
+        assert_eq!(
+            ids(&snapshots.last().unwrap().groups),
+            vec![HISTORY_KEEP as i64 + 2]
+        );
+    }
+
+    #[test]
+    fn push_keeps_unqueried_groups() {
+        let mut history = History::default();
+        history.push(7, &[group(1), group(2)], &HashSet::new());
+
+        let diff = history.push(7, &[group(3)], &HashSet::from([2])).unwrap();
+        assert_eq!((ids(&diff.joined), ids(&diff.left)), (vec![3], vec![1]));
+        let mut latest = ids(&history.targets[&7].last().unwrap().groups);
+        latest.sort();
+        assert_eq!(latest, vec![2, 3]);
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    env, fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use grammers_client::{
+    Client, Config, InitParams, InvocationError, SignInError, grammers_tl_types as tl,
+    types::PasswordToken,
+};
+use qrcode::{QrCode, render::unicode::Dense1x2};
+use tokio::time::sleep;
+
+use crate::{
+    config::MainConfig,
+    defs::{API_HASH, API_ID, DEFAULT_DC, ENV_PREFIX, LOGIN_WAIT, QR_POLL, SESSION_FILE},
+    health::Reconnect,
+    vault,
+};
+
+/// Connects with `session`; with `catch_up`, updates missed while offline are fetched
+/// from the update state saved in the session.
+pub async fn connect(session: &str, catch_up: bool) -> Result<Client> {
+    Ok(Client::connect(Config {
+        session: vault::load(session)?,
+        api_id: API_ID,
+        api_hash: API_HASH.to_string(),
+        params: InitParams {
+            reconnection_policy: Reconnect::leak(session),
+            catch_up,
+            ..Default::default()
+        },
+    })
+    .await?)
+}
+
+/// Saves `client`'s session and connects again, so a login finished by raw requests
+/// starts from a properly initialized client.
+async fn reconnect(client: Client, session: &str) -> Result<Client> {
+    vault::save(client.session(), session)?;
+    drop(client);
+    connect(session, false).await
+}
+
+pub fn prompt(message: &str) -> Result<String> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(message.as_bytes())?;
+    stdout.flush()?;
+
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    let mut line = String::new();
+    stdin.read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+/// Environment variable prefix for `session`'s login values: `CHECKBOT` for
+/// `userbot.session`, `CHECKBOT_<NAME>` for an extra `<name>.session`.
+fn env_prefix(session: &str) -> String {
+    if session == SESSION_FILE {
+        return ENV_PREFIX.to_string();
+    }
+    let stem = Path::new(session)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    format!("{}_{}", ENV_PREFIX, stem)
+}
+
+/// `{var}` itself, or the trimmed content of the file named by `{var}_FILE`.
+pub fn provided(var: &str) -> Result<Option<String>> {
+    if let Ok(value) = env::var(var) {
+        return Ok(Some(value.trim().to_string()));
+    }
+    match env::var(format!("{}_FILE", var)) {
+        Ok(path) => Ok(Some(fs::read_to_string(&path)?.trim().to_string())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Asks on the terminal, or fails right away with what to set instead when there is none.
+fn ask(var: &str, message: &str) -> Result<String> {
+    if !io::stdin().is_terminal() {
+        bail!(
+            "stdin is not a terminal; set {0} or {0}_FILE to log in non-interactively",
+            var
+        );
+    }
+    prompt(message)
+}
+
+/// A value that only exists once the login has started, such as the code Telegram sends.
+/// `{var}_FILE` may name a file that doesn't exist yet: it is waited for up to `LOGIN_WAIT`
+/// seconds, and a file left over from before `since` is ignored.
+async fn await_value(var: &str, message: &str, since: SystemTime) -> Result<String> {
+    if let Ok(value) = env::var(var) {
+        return Ok(value.trim().to_string());
+    }
+    let Ok(path) = env::var(format!("{}_FILE", var)) else {
+        return ask(var, message);
+    };
+
+    log::info!("waiting for {} to be written to {}", var, path);
+    for _ in 0..LOGIN_WAIT {
+        if let Ok(meta) = fs::metadata(&path)
+            && meta.modified()? >= since
+        {
+            let value = fs::read_to_string(&path)?.trim().to_string();
+            if !value.is_empty() {
+                return Ok(value);
+            }
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+    bail!("{} was not written to {} within {}s", var, path, LOGIN_WAIT)
+}
+
+async fn check_password(client: &Client, prefix: &str, token: PasswordToken) -> Result<()> {
+    let var = format!("{}_PASSWORD", prefix);
+    let password = match provided(&var)? {
+        Some(p) => p,
+        None => ask(
+            &var,
+            &format!(
+                "Enter the password (hint {}): ",
+                token.hint().unwrap_or("None")
+            ),
+        )?,
+    };
+
+    match client.check_password(token, password.trim()).await {
+        Ok(_) => Ok(()),
+        Err(SignInError::InvalidPassword) => bail!("wrong 2FA password in {}", var),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn code_sign_in(client: &Client, prefix: &str) -> Result<()> {
+    let var = format!("{}_PHONE", prefix);
+    let phone = match provided(&var)? {
+        Some(p) => p,
+        None => ask(&var, "Enter your phone number (international format): ")?,
+    };
+    let since = SystemTime::now();
+    let token = client.request_login_code(&phone).await?;
+    let code = await_value(
+        &format!("{}_CODE", prefix),
+        "Enter the code you received: ",
+        since,
+    )
+    .await?;
+
+    match client.sign_in(&token, &code).await {
+        Ok(_) => Ok(()),
+        Err(SignInError::PasswordRequired(token)) => check_password(client, prefix, token).await,
+        Err(SignInError::InvalidCode) => bail!("invalid login code"),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn print_qr(token: &[u8]) -> Result<()> {
+    let url = format!("tg://login?token={}", URL_SAFE_NO_PAD.encode(token));
+    // Light modules are drawn as blocks, which scans on the usual dark terminal background.
+    let qr = QrCode::new(url.as_bytes())?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    println!(
+        "Scan with Telegram (Settings > Devices > Link Desktop Device):\n{}\n{}",
+        qr, url
+    );
+
+    Ok(())
+}
+
+/// Logs in by exporting login tokens as QR codes until another signed-in device accepts one.
+async fn qr_sign_in(mut client: Client, session: &str, prefix: &str) -> Result<Client> {
+    let mut dc = client
+        .session()
+        .get_user()
+        .map(|u| u.dc)
+        .unwrap_or(DEFAULT_DC);
+    let mut shown = Vec::new();
+    let mut import = None;
+
+    loop {
+        let result = match import.take() {
+            Some(token) => {
+                client
+                    .invoke(&tl::functions::auth::ImportLoginToken { token })
+                    .await
+            }
+            None => {
+                client
+                    .invoke(&tl::functions::auth::ExportLoginToken {
+                        api_id: API_ID,
+                        api_hash: API_HASH.to_string(),
+                        except_ids: Vec::new(),
+                    })
+                    .await
+            }
+        };
+        let token = match result {
+            Ok(token) => token,
+            Err(InvocationError::Rpc(e)) if e.name == "SESSION_PASSWORD_NEEDED" => {
+                let tl::enums::account::Password::Password(password) = client
+                    .invoke(&tl::functions::account::GetPassword {})
+                    .await?;
+                check_password(&client, prefix, PasswordToken::new(password)).await?;
+                return Ok(client);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match token {
+            tl::enums::auth::LoginToken::Token(t) => {
+                if t.token != shown {
+                    print_qr(&t.token)?;
+                    shown = t.token;
+                }
+                sleep(Duration::from_secs(QR_POLL)).await;
+            }
+            // The account lives in another datacenter, which has to import the token.
+            tl::enums::auth::LoginToken::MigrateTo(m) => {
+                dc = m.dc_id;
+                client.session().set_user(0, dc, false);
+                client = reconnect(client, session).await?;
+                import = Some(m.token);
+            }
+            tl::enums::auth::LoginToken::Success(s) => {
+                let tl::enums::auth::Authorization::Authorization(auth) = s.authorization else {
+                    bail!("this account is not registered on Telegram");
+                };
+                let id = match auth.user {
+                    tl::enums::User::User(u) => u.id,
+                    tl::enums::User::Empty(u) => u.id,
+                };
+                client.session().set_user(id, dc, false);
+                return reconnect(client, session).await;
+            }
+        }
+    }
+}
+
+/// Connects `session` and signs it in if needed, by phone code or, with
+/// `<prefix>_LOGIN=qr`, by QR code. Values come from the environment or files
+/// first and the terminal last.
+pub async fn sign_in(session: &str) -> Result<Client> {
+    let client = connect(session, false).await?;
+    if client.is_authorized().await? {
+        return Ok(client);
+    }
+
+    println!("Signing in {}...", session);
+    let prefix = env_prefix(session);
+    let client = match env::var(format!("{}_LOGIN", prefix)).as_deref() {
+        Ok("qr") => qr_sign_in(client, session, &prefix).await?,
+        Ok("code") | Err(_) => {
+            code_sign_in(&client, &prefix).await?;
+            client
+        }
+        Ok(other) => bail!("unknown {}_LOGIN method: {}", prefix, other),
+    };
+    println!("Signed in!");
+
+    Ok(client)
+}
+
+/// Connects the bot, signing in with `CHECKBOT_BOT_TOKEN`, `bot_token` from the config or the terminal.
+pub async fn bot_sign_in(session: &str, config: &MainConfig) -> Result<Client> {
+    let bot = connect(session, config.catch_up_max_age.is_some()).await?;
+    if bot.is_authorized().await? {
+        return Ok(bot);
+    }
+
+    let var = format!("{}_BOT_TOKEN", ENV_PREFIX);
+    let token = match provided(&var)?.or_else(|| config.bot_token.clone()) {
+        Some(t) => t,
+        None => ask(&var, "bot token:")?,
+    };
+    bot.bot_sign_in(&token).await?;
+    println!("Signed in!");
+
+    Ok(bot)
+}
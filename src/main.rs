@@ -1,43 +1,860 @@
-use std::{io::Write, time::Duration};
+use std::{collections::HashSet, io::Write, time::Instant};
 
 use anyhow::Result;
+use chrono::Utc;
 use env_logger::Builder;
 use grammers_client::{
-    Client, Config, InitParams, InputMessage, SignInError, Update,
+    Client, InputMessage, Update,
     grammers_tl_types::{self as tl},
-    session::Session,
+    types::Message,
 };
-use tokio::time::{sleep, timeout};
 
 use crate::{
+    api::api_task,
+    audit::{AuditEntry, parse_since, record, search},
+    autocheck::auto_check,
+    batch::run_batch,
+    check::{channel_presence, check_target, decorate_result, expand_mentions, format_table},
     config::MainConfig,
-    defs::{API_HASH, API_ID, BOT_SESSION_FILE, HEART_TIME, SESSION_FILE, SUPER_ADMIN},
-    utils::{get_common_chats, get_packed_user, prompt, save_session},
+    defs::{AUDIT_SHOW, BOT_SESSION_FILE, CHECK_MAX_TARGETS, CONFIG_FILE, SESSION_FILE},
+    export::{parse_format, send_export, to_rows},
+    gban::{BanEntry, BanList, BanOutcome, format_reports},
+    health::{heart_task, next_update},
+    history::{History, format_timeline, track, watch_diff},
+    login::{bot_sign_in, sign_in},
+    panel::{login_link, panel_task},
+    policy::{AdminScope, ChatLevel, DetailLevel},
+    pool::{Pool, coverage_task},
+    roles::{Capability, Role},
+    shutdown::InFlight,
+    status::{format_status, mark_started},
+    tenant::Tenant,
+    utils::{command_args, edit_or_upload, save_session, save_task},
+    watch::{action_members, poll_task, watch_task},
 };
 
+mod api;
+mod audit;
+mod autocheck;
+mod batch;
+mod catchup;
+mod check;
 mod config;
+#[cfg(unix)]
+mod control;
 mod defs;
+mod export;
+mod gban;
+mod health;
+mod history;
+mod http;
+mod login;
+mod metrics;
+mod panel;
+mod policy;
+mod pool;
+mod risk;
+mod roles;
+mod shutdown;
+mod status;
+mod tenant;
 mod utils;
+mod vault;
+mod watch;
+mod webhook;
 
-async fn heart_task(client: Client) -> Result<()> {
-    log::info!(
-        "Heart task is running for {}",
-        client.get_me().await?.full_name()
-    );
-    loop {
-        sleep(Duration::from_secs(HEART_TIME)).await;
+async fn handle_message(pool: &Pool, bot: &Client, msg: Message) -> Result<()> {
+    let text = msg.text();
+    let chat = msg.chat();
+    let reply_id = Some(msg.clone().raw.id);
+    let global = MainConfig::read_config();
+    // In a tenant's chat every command sees only that tenant's groups and admins.
+    let tenant = global.tenant(chat.id()).cloned();
+    let config = match &tenant {
+        Some(t) => global.for_tenant(t),
+        None => global.clone(),
+    };
+    let group = config.clone().groups;
+    let requester = msg.sender().map(|s| s.id()).unwrap_or(chat.id());
+    let mut vis = config.visibility(requester, chat.id(), chat.pack().is_user());
+    if let Some(t) = &tenant {
+        vis.restrict(&t.groups);
+    }
+    // Commands touching the watchlist, ban list or bot status stay with the global admins.
+    let global_admin = tenant.is_none() && config.is_admin(requester);
+    // Tenants check only their own groups, which would read as changes in the shared history.
+    let tracked = tenant.is_none();
+    let started = Instant::now();
 
-        match timeout(
-            Duration::from_secs(30),
-            client.invoke(&tl::functions::Ping { ping_id: 0 }),
-        )
-        .await
+    if config.auto_check.threshold > 0 && group.contains(&chat.id()) {
+        let (joined, _) = action_members(&msg);
+        if !joined.is_empty() {
+            let (pool, bot, chat, id) = (pool.clone(), bot.clone(), chat.clone(), msg.id());
+            let config = config.clone();
+            let running = InFlight::start();
+            tokio::spawn(async move {
+                let _running = running;
+                if let Err(e) = auto_check(pool, bot, chat, joined, id, config, tracked).await {
+                    log::error!("auto check failed: {}", e);
+                }
+            });
+        }
+    }
+
+    if text.starts_with("/addadmin") && chat.pack().is_user() && config.is_admin(requester) {
+        let user = text.trim_start_matches("/addadmin").trim();
+        // `admins` holds moderators, so this is `/grant <uid> moderator` under the same rules.
+        let reply = if !(config.is_owner(requester) || config.can(requester, Capability::AddAdmins))
         {
-            Ok(Ok(_)) => log::info!("Heart task: successful"),
-            Ok(Err(e)) => log::warn!("Heart task: error: {}", e),
-            Err(e) => log::warn!("Heart task start failed: {}", e),
+            "权限不足"
+        } else {
+            match user.parse::<i64>() {
+                Ok(admin) if config.can_assign(requester, admin, Some(Role::Moderator)) => {
+                    let mut config = config.clone();
+                    config.admins.insert(admin);
+                    MainConfig::rewrite_config(Some(config));
+                    record(
+                        AuditEntry::new(requester, chat.id(), "/addadmin", started).target(admin),
+                    );
+                    "已添加"
+                }
+                Ok(_) => "权限不足",
+                Err(_) => "用法: /addadmin <uid>",
+            }
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if (text.starts_with("/grant") || text.starts_with("/revoke"))
+        && chat.pack().is_user()
+        && config.is_admin(requester)
+    {
+        let grant = text.starts_with("/grant");
+        let args = text.split_whitespace().skip(1).collect::<Vec<_>>();
+        let (target, role) = match (grant, &args[..]) {
+            (true, [t, r]) => (Some(*t), Role::parse(r)),
+            (false, [t]) => (Some(*t), None),
+            _ => (None, None),
+        };
+        let reply = match target {
+            Some(_) if grant && role.is_none() => {
+                "用法: /grant <uid|@username> <owner|manager|moderator|viewer>".to_string()
+            }
+            Some(target) => match pool.resolve(target).await {
+                Ok(user) if config.can_assign(requester, user, role) => {
+                    let mut config = config.clone();
+                    config.assign(user, role);
+                    MainConfig::rewrite_config(Some(config));
+                    let command = if grant { "/grant" } else { "/revoke" };
+                    record(
+                        AuditEntry::new(requester, chat.id(), command, started)
+                            .target(args.join(" ")),
+                    );
+                    match role {
+                        Some(role) => format!("已将 {} 设为 {}", user, role.name()),
+                        None => format!("已移除 {} 的角色", user),
+                    }
+                }
+                Ok(_) => "权限不足".to_string(),
+                Err(e) => e.to_string(),
+            },
+            None if grant => {
+                "用法: /grant <uid|@username> <owner|manager|moderator|viewer>".to_string()
+            }
+            None => "用法: /revoke <uid|@username>".to_string(),
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if text == "/roles" && chat.pack().is_user() && config.is_admin(requester) {
+        bot.send_message(
+            chat.clone(),
+            InputMessage::text(config.format_roles()).reply_to(reply_id),
+        )
+        .await?;
+    }
+    if text.starts_with("/addgroup")
+        && chat.pack().is_user()
+        && config.can(requester, Capability::AddGroups)
+    {
+        let user = text.trim_start_matches("/addgroup").trim();
+        let reply = match user.parse::<i64>() {
+            Ok(group) => {
+                let mut config = config.clone();
+                config.groups.insert(group);
+                MainConfig::rewrite_config(Some(config));
+                record(AuditEntry::new(requester, chat.id(), "/addgroup", started).target(group));
+                "已添加"
+            }
+            Err(_) => "用法: /addgroup <group_id>",
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if text.starts_with("/setlevel") && chat.pack().is_user() && config.is_owner(requester) {
+        let args = text
+            .trim_start_matches("/setlevel")
+            .split_whitespace()
+            .collect::<Vec<_>>();
+        let (target, level) = match args[..] {
+            [c, l] => (c.parse::<i64>().ok(), DetailLevel::parse(l)),
+            _ => (None, None),
+        };
+        let reply = match (target, level) {
+            (Some(target), Some(level)) => {
+                let mut config = config.clone();
+                config.chat_levels.retain(|c| c.chat != target);
+                config.chat_levels.push(ChatLevel {
+                    chat: target,
+                    level,
+                });
+                MainConfig::rewrite_config(Some(config));
+                record(
+                    AuditEntry::new(requester, chat.id(), "/setlevel", started)
+                        .target(args.join(" ")),
+                );
+                "已设置"
+            }
+            _ => "用法: /setlevel <chat_id> <count|names|links>",
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if text.starts_with("/setscope") && chat.pack().is_user() && config.is_owner(requester) {
+        let mut args = text.trim_start_matches("/setscope").split_whitespace();
+        let target = args.next().and_then(|a| a.parse::<i64>().ok());
+        let rest = args.collect::<Vec<_>>();
+        let groups = rest
+            .iter()
+            .map(|g| g.parse::<i64>())
+            .collect::<Result<HashSet<_>, _>>();
+        let reply = match (target, groups) {
+            (Some(target), _) if rest == ["all"] => {
+                let mut config = config.clone();
+                config.admin_scopes.retain(|s| s.admin != target);
+                MainConfig::rewrite_config(Some(config));
+                record(
+                    AuditEntry::new(requester, chat.id(), "/setscope", started)
+                        .target(format!("{} all", target)),
+                );
+                "已设置"
+            }
+            (Some(target), Ok(groups)) if !groups.is_empty() => {
+                let mut config = config.clone();
+                config.admin_scopes.retain(|s| s.admin != target);
+                config.admin_scopes.push(AdminScope {
+                    admin: target,
+                    groups,
+                });
+                MainConfig::rewrite_config(Some(config));
+                record(
+                    AuditEntry::new(requester, chat.id(), "/setscope", started)
+                        .target(rest.join(" ")),
+                );
+                "已设置"
+            }
+            _ => "用法: /setscope <admin_id> <group_id...|all>",
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if (text.starts_with("/addtenant") || text.starts_with("/deltenant"))
+        && chat.pack().is_user()
+        && config.is_owner(requester)
+    {
+        let add = text.starts_with("/addtenant");
+        let ids = text
+            .split_whitespace()
+            .skip(1)
+            .map(|i| i.parse::<i64>())
+            .collect::<Result<Vec<_>, _>>();
+        let reply = match ids.as_deref() {
+            Ok([target, new_admins @ ..]) => {
+                let mut global = global.clone();
+                let command = if add { "/addtenant" } else { "/deltenant" };
+                if add {
+                    if global.tenant(*target).is_none() {
+                        global.tenants.push(Tenant {
+                            chat: *target,
+                            ..Default::default()
+                        });
+                    }
+                    if let Some(t) = global.tenant_mut(*target) {
+                        t.admins.extend(new_admins);
+                    }
+                } else {
+                    global.tenants.retain(|t| t.chat != *target);
+                }
+                MainConfig::rewrite_config(Some(global));
+                record(
+                    AuditEntry::new(requester, chat.id(), command, started).target(
+                        text.split_whitespace()
+                            .skip(1)
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ),
+                );
+                if add { "已设置" } else { "已移除" }
+            }
+            _ if add => "用法: /addtenant <chat_id> [admin_id...]",
+            _ => "用法: /deltenant <chat_id>",
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if let Some(t) = &tenant
+        && config.is_admin(requester)
+        && let Some(command) = ["/addgroup", "/delgroup", "/addadmin", "/deladmin"]
+            .into_iter()
+            .find(|c| text.starts_with(c))
+    {
+        let reply = match text.trim_start_matches(command).trim().parse::<i64>() {
+            Ok(id) => {
+                let mut global = global.clone();
+                let (reply, changed) = tenant::modify(&mut global, t.chat, requester, command, id);
+                if changed {
+                    MainConfig::rewrite_config(Some(global));
+                    record(AuditEntry::new(requester, chat.id(), command, started).target(id));
+                }
+                reply.to_string()
+            }
+            Err(_) => format!("用法: {} <id>", command),
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if text == "/tenant"
+        && let Some(t) = &tenant
+        && config.is_admin(requester)
+    {
+        bot.send_message(
+            chat.clone(),
+            InputMessage::text(t.format()).reply_to(reply_id),
+        )
+        .await?;
+    }
+    if text.starts_with("/audit") && chat.pack().is_user() && config.is_owner(requester) {
+        let mut user = None;
+        let mut since = None;
+        let mut valid = true;
+        for arg in text.trim_start_matches("/audit").split_whitespace() {
+            match arg.parse::<i64>() {
+                Ok(id) => user = Some(id),
+                Err(_) => match parse_since(arg) {
+                    Some(t) => since = Some(t),
+                    None => valid = false,
+                },
+            }
         }
+        let entries = search(user, since);
+        let reply = if !valid {
+            "用法: /audit [uid] [7d|12h|30m|YYYY-MM-DD]".to_string()
+        } else if entries.is_empty() {
+            "没有匹配的审计记录".to_string()
+        } else {
+            let shown = entries
+                .iter()
+                .rev()
+                .take(AUDIT_SHOW)
+                .rev()
+                .map(|e| e.format())
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(
+                "共 {} 条记录, 最近 {} 条:\n{}",
+                entries.len(),
+                AUDIT_SHOW.min(entries.len()),
+                shown
+            )
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
     }
+    if text.starts_with("/history") && tracked && vis.detailed() {
+        let target = text.trim_start_matches("/history").trim();
+        let user = if target.is_empty() {
+            None
+        } else {
+            pool.resolve(target).await.ok()
+        };
+        let reply = match user {
+            Some(user) => match History::read().targets.get(&user) {
+                Some(snapshots) => format_timeline(user, snapshots, &vis),
+                None => "该用户没有查询记录".to_string(),
+            },
+            None if target.is_empty() => "目标不能为空".to_string(),
+            None => "未知用户".to_string(),
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if (text.starts_with("/watch ") || text.starts_with("/unwatch "))
+        && global_admin
+        && config.can(requester, Capability::Watch)
+    {
+        let watch = text.starts_with("/watch ");
+        let mut config = config.clone();
+        let mut lines = Vec::new();
+        for target in text.split_whitespace().skip(1) {
+            let user = match pool.resolve(target).await {
+                Ok(id) => id,
+                Err(e) => {
+                    lines.push(format!("{}: {}", target, e));
+                    continue;
+                }
+            };
+            if watch {
+                // Record a baseline so the first periodic check only reports real changes.
+                if let Ok(lookup) = pool.common_groups(user, &group).await {
+                    watch_diff(user, &lookup);
+                }
+                config.watchlist.insert(user);
+                lines.push(format!("{}: 已加入监控", target));
+            } else {
+                config.watchlist.remove(&user);
+                lines.push(format!("{}: 已取消监控", target));
+            }
+            record(
+                AuditEntry::new(
+                    requester,
+                    chat.id(),
+                    if watch { "/watch" } else { "/unwatch" },
+                    started,
+                )
+                .target(user),
+            );
+        }
+        MainConfig::rewrite_config(Some(config));
+        let reply = if lines.is_empty() {
+            "目标不能为空".to_string()
+        } else {
+            lines.join("\n")
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if text == "/status" && global_admin {
+        bot.send_message(
+            chat.clone(),
+            InputMessage::text(format_status(pool, &config)).reply_to(reply_id),
+        )
+        .await?;
+    }
+    if text == "/panel" && chat.pack().is_user() && global_admin {
+        let reply = match login_link(&config, requester)? {
+            Some(link) => format!("管理面板登录链接 (5分钟内有效, 仅可使用一次):\n{}", link),
+            None => "管理面板未启用".to_string(),
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if text == "/watchlist" && global_admin {
+        let reply = if config.watchlist.is_empty() {
+            "监控列表为空".to_string()
+        } else {
+            let mut list = config.watchlist.iter().collect::<Vec<_>>();
+            list.sort();
+            format!(
+                "监控用户 {} 个:\n{}",
+                list.len(),
+                list.iter()
+                    .map(|u| u.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )
+        };
+        bot.send_message(chat.clone(), InputMessage::text(reply).reply_to(reply_id))
+            .await?;
+    }
+    if (text.starts_with("/gban ") || text.starts_with("/ungban "))
+        && global_admin
+        && config.can(requester, Capability::Ban)
+    {
+        let ban = text.starts_with("/gban ");
+        let mut dry_run = false;
+        let mut words = Vec::new();
+        for arg in text.split_whitespace().skip(1) {
+            match arg {
+                "--dry" => dry_run = true,
+                _ => words.push(arg),
+            }
+        }
+        let sended_msg = bot
+            .send_message(
+                chat.clone(),
+                InputMessage::text("处理中...").reply_to(reply_id),
+            )
+            .await?;
+        let Some((target, reason)) = words.split_first() else {
+            bot.edit_message(chat, sended_msg.id(), "目标不能为空")
+                .await?;
+            return Ok(());
+        };
+        let user = match pool.resolve(target).await {
+            Ok(id) => id,
+            Err(e) => {
+                bot.edit_message(chat, sended_msg.id(), e.to_string())
+                    .await?;
+                return Ok(());
+            }
+        };
+        if pool.is_self(bot, user).await? {
+            bot.edit_message(chat, sended_msg.id(), "不能封禁自身")
+                .await?;
+            return Ok(());
+        }
+        let reports = gban::apply(pool, user, &group, ban, dry_run).await?;
+        if !dry_run {
+            let mut bans = BanList::read();
+            if ban {
+                bans.add(BanEntry {
+                    user,
+                    reason: (!reason.is_empty()).then(|| reason.join(" ")),
+                    by: requester,
+                    time: chrono::Utc::now(),
+                });
+            } else {
+                bans.remove(user);
+            }
+            bans.write();
+            record(
+                AuditEntry::new(
+                    requester,
+                    chat.id(),
+                    if ban { "/gban" } else { "/ungban" },
+                    started,
+                )
+                .target(user)
+                .count(
+                    reports
+                        .iter()
+                        .filter(|r| matches!(r.outcome, BanOutcome::Done))
+                        .count(),
+                ),
+            );
+        }
+        let reply = format!(
+            "{} {}{}{}\n{}",
+            if ban {
+                "全局封禁"
+            } else {
+                "解除全局封禁"
+            },
+            user,
+            if ban && !reason.is_empty() {
+                format!(" (原因: {})", reason.join(" "))
+            } else {
+                String::new()
+            },
+            if dry_run { " [试运行]" } else { "" },
+            format_reports(&reports, &vis)
+        );
+        bot.edit_message(chat.clone(), sended_msg.id(), reply)
+            .await?;
+    }
+    if text.starts_with("/check") {
+        let sended_msg = bot
+            .send_message(
+                chat.clone(),
+                InputMessage::text("查询中...").reply_to(reply_id),
+            )
+            .await?;
+        if let Some(media) = msg.media() {
+            if !chat.pack().is_user() || !config.can(requester, Capability::BatchCheck) {
+                bot.edit_message(chat, sended_msg.id(), "仅管理员可批量查询")
+                    .await?;
+                return Ok(());
+            }
+            let (pool, bot) = (pool.clone(), bot.clone());
+            let chat_id = chat.id();
+            // Started before spawning, so a shutdown right after still waits for the batch.
+            let running = InFlight::start();
+            tokio::spawn(async move {
+                let _running = running;
+                match run_batch(pool, bot, sended_msg, media, group, vis, tracked).await {
+                    Ok(n) => record(
+                        AuditEntry::new(requester, chat_id, "/check batch", started).count(n),
+                    ),
+                    Err(e) => log::error!("batch check failed: {}", e),
+                }
+            });
+            return Ok(());
+        }
+        let args = expand_mentions(text, msg.fmt_entities());
+        let (format, targets) = match parse_format(command_args(&args, "/check")) {
+            Ok(f) => f,
+            Err(e) => {
+                bot.edit_message(chat, sended_msg.id(), e.to_string())
+                    .await?;
+                return Ok(());
+            }
+        };
+        if targets.is_empty() {
+            bot.edit_message(chat, sended_msg.id(), "目标不能为空")
+                .await?;
+            return Ok(());
+        }
+        if targets.len() > 1 && !config.can(requester, Capability::BatchCheck) {
+            bot.edit_message(chat, sended_msg.id(), "仅管理员可批量查询")
+                .await?;
+            return Ok(());
+        }
+        if targets.len() > CHECK_MAX_TARGETS {
+            bot.edit_message(
+                chat,
+                sended_msg.id(),
+                format!(
+                    "一次最多查询 {} 个目标, 更多目标请上传文件批量查询",
+                    CHECK_MAX_TARGETS
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+        if let Some(format) = format {
+            if !(vis.detailed() && config.can(requester, Capability::Export)) {
+                bot.edit_message(chat, sended_msg.id(), "仅管理员可导出")
+                    .await?;
+                return Ok(());
+            }
+            let mut export = Vec::new();
+            let mut failed = Vec::new();
+            for target in targets {
+                match check_target(pool, bot, target, &group).await {
+                    Ok((user, lookup)) => {
+                        if tracked {
+                            track(user, &lookup);
+                        }
+                        let list = &lookup.groups;
+                        if let Some(note) = lookup.incomplete_note() {
+                            failed.push(format!("{}: {}", target, note));
+                        }
+                        record(
+                            AuditEntry::new(requester, chat.id(), "/check export", started)
+                                .target(user)
+                                .count(list.len()),
+                        );
+                        export.extend(to_rows(
+                            user,
+                            pool.get_username(user).await,
+                            &vis.filter(list),
+                        ))
+                    }
+                    Err(e) => failed.push(format!("{}: {}", target, e)),
+                }
+            }
+            let caption = if failed.is_empty() {
+                "导出完成".to_string()
+            } else {
+                format!("导出完成, 以下目标失败或不完整:\n{}", failed.join("\n"))
+            };
+            send_export(bot, chat.clone(), reply_id, &caption, &export, format).await?;
+            bot.delete_messages(chat, &[sended_msg.id()]).await?;
+            return Ok(());
+        }
+        if targets.len() > 1 {
+            let mut rows = Vec::new();
+            for target in targets {
+                let row = check_target(pool, bot, target, &group).await;
+                if let Ok((user, lookup)) = &row {
+                    if tracked {
+                        track(*user, lookup);
+                    }
+                    record(
+                        AuditEntry::new(requester, chat.id(), "/check", started)
+                            .target(*user)
+                            .count(lookup.groups.len()),
+                    );
+                }
+                rows.push((target.to_string(), row));
+            }
+            let table = format_table(&rows, &vis, &config.risk);
+            edit_or_upload(bot, chat, sended_msg.id(), table, "check.txt").await?;
+            return Ok(());
+        }
+        let user = match pool.resolve(targets[0]).await {
+            Ok(id) => id,
+            Err(e) => {
+                bot.edit_message(chat.clone(), sended_msg.id(), e.to_string())
+                    .await?;
+                return Ok(());
+            }
+        };
+        if pool.is_self(bot, user).await? {
+            bot.edit_message(chat, sended_msg.id(), "不能查询自身")
+                .await?;
+            return Ok(());
+        }
+        let lookup = match pool.common_groups(user, &group).await {
+            Ok(l) => l,
+            Err(e) => {
+                bot.edit_message(chat, sended_msg.id(), e.to_string())
+                    .await?;
+                return Ok(());
+            }
+        };
+        let diff = tracked.then(|| track(user, &lookup)).flatten();
+        let result = decorate_result(
+            user,
+            &lookup,
+            &vis,
+            &config,
+            diff.as_ref(),
+            tenant.is_none(),
+        );
+        edit_or_upload(bot, chat.clone(), sended_msg.id(), result, "check.txt").await?;
+        record(
+            AuditEntry::new(requester, chat.id(), "/check", started)
+                .target(user)
+                .count(lookup.groups.len()),
+        );
+    }
+    if text.starts_with("/reply") {
+        let (format, forwarded, as_channel) = match parse_format(command_args(text, "/reply")) {
+            Ok((f, args)) => (f, args.contains(&"--fwd"), args.contains(&"--channel")),
+            Err(e) => {
+                bot.send_message(
+                    chat.clone(),
+                    InputMessage::text(e.to_string()).reply_to(reply_id),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+        let reply = match msg.get_reply().await? {
+            Some(r) => r,
+            None => {
+                bot.send_message(
+                    chat.clone(),
+                    InputMessage::text("请回复消息").reply_to(reply_id),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+        let sender_id = if forwarded {
+            let from = match reply.forward_header() {
+                Some(tl::enums::MessageFwdHeader::Header(h)) => h.from_id,
+                None => {
+                    bot.send_message(
+                        chat.clone(),
+                        InputMessage::text("该消息不是转发消息").reply_to(reply_id),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+            match from {
+                Some(tl::enums::Peer::User(u)) => u.user_id,
+                Some(_) => {
+                    bot.send_message(
+                        chat.clone(),
+                        InputMessage::text("转发来源不是用户").reply_to(reply_id),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+                None => {
+                    bot.send_message(
+                        chat.clone(),
+                        InputMessage::text("原作者隐藏了转发来源").reply_to(reply_id),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            }
+        } else {
+            match reply.sender() {
+                Some(s) if s.pack().is_user() => s.id(),
+                Some(s) => {
+                    // Anonymous admins post as the group itself, channels as the channel.
+                    let text = if s.id() == chat.id() {
+                        "该消息由匿名管理员发送, 无法查询发送者".to_string()
+                    } else if as_channel {
+                        match channel_presence(bot, &s, &group).await {
+                            Ok(t) => t,
+                            Err(e) => format!("获取频道信息失败: {}", e),
+                        }
+                    } else {
+                        format!(
+                            "该消息以频道 {} ({}) 身份发送, 无法查询用户, 可使用 /reply --channel 查询该频道及其讨论群是否为监控群",
+                            s.name(),
+                            s.id()
+                        )
+                    };
+                    bot.send_message(chat.clone(), InputMessage::text(text).reply_to(reply_id))
+                        .await?;
+                    return Ok(());
+                }
+                None => {
+                    bot.send_message(
+                        chat.clone(),
+                        InputMessage::text("无法获取发送者").reply_to(reply_id),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            }
+        };
+        if pool.is_self(bot, sender_id).await? {
+            bot.send_message(
+                chat.clone(),
+                InputMessage::text("不能查询自身").reply_to(reply_id),
+            )
+            .await?;
+            return Ok(());
+        }
+        let sended_msg = bot
+            .send_message(
+                chat.clone(),
+                InputMessage::text("查询中...").reply_to(reply_id),
+            )
+            .await?;
+        if format.is_some() && !(vis.detailed() && config.can(requester, Capability::Export)) {
+            bot.edit_message(chat, sended_msg.id(), "仅管理员可导出")
+                .await?;
+            return Ok(());
+        }
+        let lookup = match pool.common_groups(sender_id, &group).await {
+            Ok(l) => l,
+            Err(e) => {
+                bot.edit_message(chat, sended_msg.id(), e.to_string())
+                    .await?;
+                return Ok(());
+            }
+        };
+        let diff = tracked.then(|| track(sender_id, &lookup)).flatten();
+        record(
+            AuditEntry::new(requester, chat.id(), "/reply", started)
+                .target(sender_id)
+                .count(lookup.groups.len()),
+        );
+        if let Some(format) = format {
+            let rows = to_rows(
+                sender_id,
+                pool.get_username(sender_id).await,
+                &vis.filter(&lookup.groups),
+            );
+            let caption = match lookup.incomplete_note() {
+                Some(note) => format!("导出完成\n{}", note),
+                None => "导出完成".to_string(),
+            };
+            send_export(bot, chat.clone(), reply_id, &caption, &rows, format).await?;
+            bot.delete_messages(chat, &[sended_msg.id()]).await?;
+            return Ok(());
+        }
+        let result = decorate_result(
+            sender_id,
+            &lookup,
+            &vis,
+            &config,
+            diff.as_ref(),
+            tenant.is_none(),
+        );
+        edit_or_upload(bot, chat.clone(), sended_msg.id(), result, "check.txt").await?;
+    }
+
+    Ok(())
 }
 
 #[tokio::main]
@@ -58,327 +875,104 @@ async fn main() -> Result<()> {
         )
     });
     builder.filter_level(log::LevelFilter::Info).init();
+    mark_started();
     log::info!("Connecting to Telegram...");
-    let client = Client::connect(Config {
-        session: Session::load_file_or_create(SESSION_FILE)?,
-        api_id: API_ID,
-        api_hash: API_HASH.to_string(),
-        params: InitParams {
-            ..Default::default()
-        },
-    })
-    .await?;
-    let bot = Client::connect(Config {
-        session: Session::load_file_or_create(BOT_SESSION_FILE)?,
-        api_id: API_ID,
-        api_hash: API_HASH.to_string(),
-        params: InitParams {
-            ..Default::default()
-        },
-    })
-    .await?;
+    MainConfig::init();
+    // Refuse to start on an invalid config rather than running with defaults.
+    MainConfig::try_read().map_err(|e| anyhow::anyhow!("{} is invalid: {}", CONFIG_FILE, e))?;
+    let config = MainConfig::read_config();
+    if std::env::args().nth(1).as_deref() == Some("encrypt-sessions") {
+        let sessions = [SESSION_FILE, BOT_SESSION_FILE]
+            .into_iter()
+            .map(String::from)
+            .chain(config.userbots.iter().cloned())
+            .collect::<Vec<_>>();
+        return vault::encrypt_sessions(&sessions);
+    }
+    let mut accounts = Vec::new();
+    for session in [SESSION_FILE.to_string()]
+        .into_iter()
+        .chain(config.userbots.iter().cloned())
+    {
+        let client = sign_in(&session).await?;
+        accounts.push((session, client));
+    }
+    let pool = Pool::new(accounts);
+    let bot = bot_sign_in(BOT_SESSION_FILE, &config).await?;
     log::info!("Connected!");
 
-    if !client.is_authorized().await? {
-        println!("Signing in...");
-        let phone = prompt("Enter your phone number (international format): ")?;
-        let token = client.request_login_code(&phone).await?;
-        let code = prompt("Enter the code you received: ")?;
-        let signed_in = client.sign_in(&token, &code).await;
-        match signed_in {
-            Err(SignInError::PasswordRequired(password_token)) => {
-                let hint = password_token.hint().unwrap_or("None");
-                let prompt_message = format!("Enter the password (hint {}): ", &hint);
-                let password = prompt(prompt_message.as_str())?;
-
-                client
-                    .check_password(password_token, password.trim())
-                    .await?;
-            }
-            Ok(_) => (),
-            Err(e) => panic!("{}", e),
-        };
-        println!("Signed in!");
+    save_session(&pool, &bot);
+    for account in pool.accounts() {
+        tokio::spawn(heart_task(account.session.clone(), account.client.clone()));
+        tokio::spawn(watch_task(
+            account.session.clone(),
+            account.client.clone(),
+            pool.clone(),
+            bot.clone(),
+        ));
     }
-
-    if !bot.is_authorized().await? {
-        let token = prompt("bot token:")?;
-        bot.bot_sign_in(&token).await?;
-        println!("Signed in!");
+    tokio::spawn(heart_task(BOT_SESSION_FILE.to_string(), bot.clone()));
+    tokio::spawn(poll_task(pool.clone(), bot.clone()));
+    tokio::spawn(coverage_task(pool.clone()));
+    tokio::spawn(save_task(pool.clone(), bot.clone()));
+    if let Some(addr) = config.api.addr.clone() {
+        tokio::spawn(api_task(addr, pool.clone(), bot.clone()));
+    }
+    if let Some(addr) = config.panel.addr.clone() {
+        tokio::spawn(panel_task(addr, pool.clone()));
+    }
+    #[cfg(unix)]
+    if let Some(path) = config.control_socket.clone() {
+        tokio::spawn(control::control_task(path, pool.clone(), bot.clone()));
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = config.metrics_addr.clone() {
+        tokio::spawn(metrics::metrics_task(addr));
+    }
+    #[cfg(not(feature = "metrics"))]
+    if config.metrics_addr.is_some() {
+        log::warn!("metrics_addr is set but the metrics feature is not enabled");
     }
 
-    save_session(&client, &bot);
-    MainConfig::init();
-    tokio::spawn(heart_task(client.clone()));
-    tokio::spawn(heart_task(bot.clone()));
-
+    let online = Utc::now();
+    let shutdown = shutdown::signal();
+    tokio::pin!(shutdown);
+    let mut restart = false;
     loop {
-        let update = bot.next_update().await?;
-        match update {
-            Update::NewMessage(msg) => {
-                let text = msg.text();
-                let chat = msg.chat();
-                let reply_id = Some(msg.clone().raw.id);
-                let config = MainConfig::read_config();
-                let group = config.clone().groups;
-                let admins = config.clone().admins;
-
-                if text.starts_with("/addadmin")
-                    && chat.pack().is_user()
-                    && chat.id() == SUPER_ADMIN
-                {
-                    let mut config = config.clone();
-                    let mut admins = config.admins.clone();
-                    let user = text.trim_start_matches("/addadmin").trim();
-                    let admin = user.parse::<i64>().unwrap();
-                    admins.insert(admin);
-                    config.admins = admins;
-                    MainConfig::rewrite_config(Some(config));
-                    bot.send_message(
-                        chat.clone(),
-                        InputMessage::text("已添加").reply_to(reply_id),
-                    )
-                    .await?;
-                }
-                if text.starts_with("/addgroup")
-                    && chat.pack().is_user()
-                    && chat.id() == SUPER_ADMIN
-                {
-                    let mut config = config.clone();
-                    let mut groups = config.groups.clone();
-                    let user = text.trim_start_matches("/addgroup").trim();
-                    let group = user.parse::<i64>().unwrap();
-                    groups.insert(group);
-                    config.groups = groups;
-                    MainConfig::rewrite_config(Some(config));
-                    bot.send_message(
-                        chat.clone(),
-                        InputMessage::text("已添加").reply_to(reply_id),
-                    )
-                    .await?;
-                }
-                if text.starts_with("/check") {
-                    let user = text.trim_start_matches("/check").trim();
-                    let sended_msg = bot
-                        .send_message(
-                            chat.clone(),
-                            InputMessage::text("查询中...").reply_to(reply_id),
-                        )
-                        .await?;
-                    if user.is_empty() {
-                        bot.edit_message(chat, sended_msg.id(), "目标不能为空")
-                            .await?;
-                        continue;
-                    }
-                    let packed_user = if user.starts_with("@") {
-                        let id = match client
-                            .resolve_username(user.trim_start_matches("@").trim())
-                            .await?
-                        {
-                            Some(s) => s,
-                            None => {
-                                bot.edit_message(chat, sended_msg.id(), "未知用户").await?;
-                                continue;
-                            }
-                        }
-                        .id();
-                        match get_packed_user(&client, id).await {
-                            Ok(p) => p,
-                            Err(_) => {
-                                bot.edit_message(
-                                    chat.clone(),
-                                    sended_msg.id(),
-                                    "不能获取用户access_hash，你能确保我见过吗",
-                                )
-                                .await?;
-                                continue;
-                            }
-                        }
-                    } else {
-                        let id = match user.parse::<i64>() {
-                            Ok(i) => i,
-                            Err(_) => {
-                                bot.edit_message(chat, sended_msg.id(), "id解析失败")
-                                    .await?;
-                                continue;
-                            }
-                        };
-                        match get_packed_user(&client, id).await {
-                            Ok(p) => p,
-                            Err(_) => {
-                                bot.edit_message(
-                                    chat.clone(),
-                                    sended_msg.id(),
-                                    "不能获取用户access_hash，你能确保我见过吗",
-                                )
-                                .await?;
-                                continue;
-                            }
-                        }
-                    };
-                    if packed_user.id == client.get_me().await?.id()
-                        || packed_user.id == bot.get_me().await?.id()
-                    {
-                        bot.edit_message(chat, sended_msg.id(), "不能查询自身")
-                            .await?;
-                        continue;
-                    }
-                    let mut count = 0;
-                    let mut admin_list = Vec::new();
-                    let groups = get_common_chats(&client, &packed_user, 100).await?;
-                    for i in groups {
-                        if group.contains(&i.id()) {
-                            let mut title = String::new();
-                            let mut username = String::new();
-                            match i {
-                                tl::enums::Chat::Channel(c) => {
-                                    title = c.title;
-                                    if let Some(s) = c.username {
-                                        username = format!("@{}", s);
-                                    } else {
-                                        username = "N/A".to_string();
-                                    }
-                                }
-                                _ => {}
-                            }
-                            admin_list.insert(count, (title, username));
-                            count += 1;
-                        }
-                    }
-
-                    if count == 0 {
-                        bot.edit_message(chat.clone(), sended_msg.id(), "未查询到共同群")
-                            .await?;
-                        continue;
-                    }
-                    let send_msg = {
-                        if admins.contains(&chat.id()) && msg.chat().pack().is_user() {
-                            let s: String = admin_list
-                                .iter()
-                                .map(|(t, u)| format!("{} - {}", t, u))
-                                .collect::<Vec<_>>()
-                                .join("\n");
-
-                            format!(
-                                r#"与用户 {} 共同群 {} 个
-具体群组:
-{}"#,
-                                packed_user.id.to_string(),
-                                count,
-                                s
-                            )
-                        } else {
-                            format!("与用户 {} 共同群 {} 个", packed_user.id.to_string(), count)
-                        }
-                    };
-                    bot.edit_message(chat.clone(), sended_msg.id(), send_msg)
-                        .await?;
-                }
-                if text == "/reply" {
-                    let reply = match msg.get_reply().await? {
-                        Some(r) => r,
-                        None => {
-                            bot.send_message(
-                                chat.clone(),
-                                InputMessage::text("请回复消息").reply_to(reply_id),
-                            )
-                            .await?;
-                            continue;
-                        }
-                    };
-                    let sender = match reply.sender() {
-                        Some(s) => s,
-                        None => {
-                            bot.send_message(
-                                chat.clone(),
-                                InputMessage::text("无法获取发送者").reply_to(reply_id),
-                            )
-                            .await?;
-                            continue;
-                        }
-                    }
-                    .pack();
-                    if sender.id == client.get_me().await?.id()
-                        || sender.id == bot.get_me().await?.id()
-                    {
-                        bot.send_message(
-                            chat.clone(),
-                            InputMessage::text("不能查询自身").reply_to(reply_id),
-                        )
-                        .await?;
-                        continue;
-                    }
-                    let sended_msg = bot
-                        .send_message(
-                            chat.clone(),
-                            InputMessage::text("查询中...").reply_to(reply_id),
-                        )
-                        .await?;
-                    let sender = match get_packed_user(&client, sender.id).await {
-                        Ok(p) => p,
-                        Err(_) => {
-                            bot.edit_message(
-                                chat.clone(),
-                                sended_msg.id(),
-                                "不能获取用户access_hash，你能确保我见过吗",
-                            )
-                            .await?;
-                            continue;
-                        }
-                    };
-                    let mut count = 0;
-                    let mut admin_list = Vec::new();
-                    let groups = get_common_chats(&client, &sender, 100).await?;
-                    for i in groups {
-                        if group.contains(&i.id()) {
-                            let mut title = String::new();
-                            let mut username = String::new();
-                            match i {
-                                tl::enums::Chat::Channel(c) => {
-                                    title = c.title;
-                                    if let Some(s) = c.username {
-                                        username = format!("@{}", s);
-                                    } else {
-                                        username = "N/A".to_string();
-                                    }
-                                }
-                                _ => {}
-                            }
-                            admin_list.insert(count, (title, username));
-                            count += 1;
-                        }
-                    }
-
-                    if count == 0 {
-                        bot.edit_message(chat.clone(), sended_msg.id(), "未查询到共同群")
-                            .await?;
-                        continue;
-                    }
-                    let send_msg = {
-                        if admins.contains(&chat.id()) && msg.chat().pack().is_user() {
-                            let s: String = admin_list
-                                .iter()
-                                .map(|(t, u)| format!("{} - {}", t, u))
-                                .collect::<Vec<_>>()
-                                .join("\n");
-
-                            format!(
-                                r#"与用户 {} 共同群 {} 个
-具体群组:
-{}"#,
-                                sender.id.to_string(),
-                                count,
-                                s
-                            )
-                        } else {
-                            format!("与用户 {} 共同群 {} 个", sender.id.to_string(), count)
-                        }
-                    };
-                    bot.edit_message(chat.clone(), sended_msg.id(), send_msg)
-                        .await?;
+        let update = tokio::select! {
+            update = next_update(BOT_SESSION_FILE, &bot) => update,
+            _ = &mut shutdown => break,
+            _ = health::dead() => {
+                restart = true;
+                break;
+            }
+        };
+        if let Update::NewMessage(msg) = update {
+            if catchup::replayed(&msg, online) {
+                continue;
+            }
+            // Marked before running, so a crash midway never runs a command twice.
+            catchup::mark_handled(&msg);
+            match catchup::skip_stale(&bot, &msg, online).await {
+                Ok(false) => {}
+                Ok(true) => continue,
+                Err(e) => {
+                    log::error!("answer stale command failed: {}", e);
+                    continue;
                 }
             }
-            _ => {}
+            let text = msg.text().to_string();
+            let result = handle_message(&pool, &bot, msg).await;
+            metrics::command(&text, result.is_ok());
+            if let Err(e) = result {
+                log::error!("handle message failed: {}", e);
+            }
         }
     }
+
+    shutdown::finish(&pool, &bot).await;
+    if restart {
+        shutdown::restart()?;
+    }
+    Ok(())
 }
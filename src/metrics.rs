@@ -0,0 +1,183 @@
+// Recording is always compiled in and cheap; only the exporter needs the `metrics` feature.
+#![cfg_attr(not(feature = "metrics"), allow(dead_code))]
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use crate::utils::command_name;
+
+/// Upper bounds in seconds of the `GetCommonChats` latency buckets.
+const BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+struct Metrics {
+    commands: BTreeMap<(String, &'static str), u64>,
+    common_chats: Histogram,
+    common_chats_pages: u64,
+    access_hash_failures: BTreeMap<&'static str, u64>,
+    flood_waits: u64,
+    heartbeats: BTreeMap<(String, &'static str), u64>,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    commands: BTreeMap::new(),
+    common_chats: Histogram {
+        buckets: [0; BUCKETS.len()],
+        sum: 0.0,
+        count: 0,
+    },
+    common_chats_pages: 0,
+    access_hash_failures: BTreeMap::new(),
+    flood_waits: 0,
+    heartbeats: BTreeMap::new(),
+});
+
+fn outcome(ok: bool) -> &'static str {
+    if ok { "ok" } else { "error" }
+}
+
+/// Counts a handled message if it starts with a known command, e.g. `/check@bot`.
+pub fn command(text: &str, ok: bool) {
+    let Some(name) = command_name(text) else {
+        return;
+    };
+
+    *METRICS
+        .lock()
+        .unwrap()
+        .commands
+        .entry((name.to_string(), outcome(ok)))
+        .or_default() += 1;
+}
+
+pub fn common_chats(elapsed: Duration, pages: usize) {
+    let mut m = METRICS.lock().unwrap();
+    let secs = elapsed.as_secs_f64();
+    for (i, le) in BUCKETS.iter().enumerate() {
+        if secs <= *le {
+            m.common_chats.buckets[i] += 1;
+        }
+    }
+    m.common_chats.sum += secs;
+    m.common_chats.count += 1;
+    m.common_chats_pages += pages as u64;
+}
+
+/// `path` is the lookup that failed: `get_users` or `dialogs`.
+pub fn access_hash_failure(path: &'static str) {
+    *METRICS
+        .lock()
+        .unwrap()
+        .access_hash_failures
+        .entry(path)
+        .or_default() += 1;
+}
+
+pub fn flood_wait() {
+    METRICS.lock().unwrap().flood_waits += 1;
+}
+
+pub fn heartbeat(session: &str, ok: bool) {
+    *METRICS
+        .lock()
+        .unwrap()
+        .heartbeats
+        .entry((session.to_string(), outcome(ok)))
+        .or_default() += 1;
+}
+
+/// Prometheus text exposition format.
+#[cfg(feature = "metrics")]
+pub fn render() -> String {
+    use std::fmt::Write;
+
+    let m = METRICS.lock().unwrap();
+    let mut s = String::new();
+
+    s.push_str("# HELP checkbot_commands_total Bot commands handled, by command and outcome.\n");
+    s.push_str("# TYPE checkbot_commands_total counter\n");
+    for ((command, outcome), n) in &m.commands {
+        let _ = writeln!(
+            s,
+            "checkbot_commands_total{{command=\"{}\",outcome=\"{}\"}} {}",
+            command, outcome, n
+        );
+    }
+
+    s.push_str("# HELP checkbot_common_chats_seconds Latency of a full GetCommonChats lookup.\n");
+    s.push_str("# TYPE checkbot_common_chats_seconds histogram\n");
+    for (le, n) in BUCKETS.iter().zip(m.common_chats.buckets) {
+        let _ = writeln!(
+            s,
+            "checkbot_common_chats_seconds_bucket{{le=\"{}\"}} {}",
+            le, n
+        );
+    }
+    let _ = writeln!(
+        s,
+        "checkbot_common_chats_seconds_bucket{{le=\"+Inf\"}} {}",
+        m.common_chats.count
+    );
+    let _ = writeln!(
+        s,
+        "checkbot_common_chats_seconds_sum {}",
+        m.common_chats.sum
+    );
+    let _ = writeln!(
+        s,
+        "checkbot_common_chats_seconds_count {}",
+        m.common_chats.count
+    );
+
+    s.push_str("# HELP checkbot_common_chats_pages_total Pages fetched by GetCommonChats.\n");
+    s.push_str("# TYPE checkbot_common_chats_pages_total counter\n");
+    let _ = writeln!(
+        s,
+        "checkbot_common_chats_pages_total {}",
+        m.common_chats_pages
+    );
+
+    s.push_str("# HELP checkbot_access_hash_failures_total Failed access hash lookups, by path.\n");
+    s.push_str("# TYPE checkbot_access_hash_failures_total counter\n");
+    for (path, n) in &m.access_hash_failures {
+        let _ = writeln!(
+            s,
+            "checkbot_access_hash_failures_total{{path=\"{}\"}} {}",
+            path, n
+        );
+    }
+
+    s.push_str("# HELP checkbot_flood_waits_total FLOOD_WAIT errors that put a userbot aside.\n");
+    s.push_str("# TYPE checkbot_flood_waits_total counter\n");
+    let _ = writeln!(s, "checkbot_flood_waits_total {}", m.flood_waits);
+
+    s.push_str("# HELP checkbot_heartbeats_total Heartbeat pings, by session and outcome.\n");
+    s.push_str("# TYPE checkbot_heartbeats_total counter\n");
+    for ((session, outcome), n) in &m.heartbeats {
+        let _ = writeln!(
+            s,
+            "checkbot_heartbeats_total{{session=\"{}\",outcome=\"{}\"}} {}",
+            session, outcome, n
+        );
+    }
+
+    s
+}
+
+#[cfg(feature = "metrics")]
+pub async fn metrics_task(addr: String) -> anyhow::Result<()> {
+    use crate::http::{Response, serve};
+
+    serve("metrics", addr, |req| async move {
+        match (req.method.as_str(), req.path.as_str()) {
+            ("GET", "/metrics") => Response::new(200, "text/plain; version=0.0.4", render()),
+            (_, "/metrics") => Response::text(405, "method not allowed"),
+            _ => Response::text(404, "not found"),
+        }
+    })
+    .await
+}
@@ -0,0 +1,392 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{AuditEntry, parse_since, record, search},
+    check::CommonGroup,
+    config::MainConfig,
+    defs::{PANEL_LINK_TTL, PANEL_SESSION_TTL, PANEL_SHOW},
+    history::History,
+    http::{Request, Response, serve},
+    policy::Visibility,
+    pool::Pool,
+    roles::{Capability, Role},
+    utils::monitored_chats,
+};
+
+/// The web panel is off unless `addr` is set; `url` is the address put in login
+/// links, when it differs from `http://{addr}` (e.g. behind a reverse proxy).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PanelConfig {
+    pub addr: Option<String>,
+    pub url: Option<String>,
+}
+
+const COOKIE: &str = "panel";
+
+/// One-time login tokens and the sessions they were exchanged for, both `token -> (user, expiry)`.
+static LINKS: Mutex<BTreeMap<String, (i64, Instant)>> = Mutex::new(BTreeMap::new());
+static SESSIONS: Mutex<BTreeMap<String, (i64, Instant)>> = Mutex::new(BTreeMap::new());
+
+fn random_token() -> Result<String> {
+    let mut buf = [0u8; 24];
+    getrandom::getrandom(&mut buf).map_err(|e| anyhow::anyhow!("getrandom: {}", e))?;
+    Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Takes `token` out of `store` if it hasn't expired, dropping expired ones on the way.
+fn take(store: &Mutex<BTreeMap<String, (i64, Instant)>>, token: &str, once: bool) -> Option<i64> {
+    let mut store = store.lock().unwrap();
+    let now = Instant::now();
+    store.retain(|_, (_, expiry)| *expiry > now);
+    if once {
+        store.remove(token).map(|(user, _)| user)
+    } else {
+        store.get(token).map(|(user, _)| *user)
+    }
+}
+
+/// A link that logs `user` in once within `PANEL_LINK_TTL` seconds, or `None` when the panel is off.
+pub fn login_link(config: &MainConfig, user: i64) -> Result<Option<String>> {
+    let Some(addr) = &config.panel.addr else {
+        return Ok(None);
+    };
+    let base = config
+        .panel
+        .url
+        .clone()
+        .unwrap_or_else(|| format!("http://{}", addr));
+    let token = random_token()?;
+    LINKS.lock().unwrap().insert(
+        token.clone(),
+        (user, Instant::now() + Duration::from_secs(PANEL_LINK_TTL)),
+    );
+
+    Ok(Some(format!(
+        "{}/login?token={}",
+        base.trim_end_matches('/'),
+        token
+    )))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The audit log link is only shown to owners, who are the only ones allowed to open it.
+fn page(title: &str, body: &str, owner: bool) -> Response {
+    Response::html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{0}</title>\
+         <style>body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse}}\
+         td,th{{border:1px solid #ccc;padding:4px 8px;text-align:left}}</style></head><body>\
+         <nav><a href=\"/\">群组与管理员</a> | <a href=\"/history\">查询历史</a> | \
+         {2}<a href=\"/logout\">退出</a></nav><h2>{0}</h2>{1}</body></html>",
+        escape(title),
+        body,
+        if owner {
+            "<a href=\"/audit\">审计日志</a> | "
+        } else {
+            ""
+        }
+    ))
+}
+
+fn id_form(action: &str, label: &str) -> String {
+    format!(
+        "<form method=\"post\" action=\"{}\"><input name=\"id\" placeholder=\"id\"> \
+         <button>{}</button></form>",
+        action, label
+    )
+}
+
+fn remove_button(action: &str, id: i64) -> String {
+    format!(
+        "<form method=\"post\" action=\"{}\" style=\"margin:0\">\
+         <input type=\"hidden\" name=\"id\" value=\"{}\"><button>移除</button></form>",
+        action, id
+    )
+}
+
+async fn index(pool: &Pool, config: &MainConfig, user: i64, vis: &Visibility) -> Response {
+    let edit_groups = config.can(user, Capability::AddGroups);
+    let mut titles = HashMap::new();
+    for account in pool.accounts() {
+        match monitored_chats(&account.client, &config.groups).await {
+            Ok(chats) => titles.extend(chats.into_iter().map(|c| {
+                (
+                    c.id(),
+                    (c.name().to_string(), c.username().map(String::from)),
+                )
+            })),
+            Err(e) => log::warn!("panel: list chats of {} failed: {}", account.session, e),
+        }
+    }
+
+    let mut groups = config.groups.iter().copied().collect::<Vec<_>>();
+    groups.sort();
+    let mut html =
+        String::from("<h3>监控群</h3><table><tr><th>ID</th><th>群名</th><th>用户名</th>");
+    if edit_groups {
+        html.push_str("<th></th>");
+    }
+    html.push_str("</tr>");
+    for id in groups {
+        let (title, username) = match titles.get(&id) {
+            Some((t, u)) => {
+                let group = CommonGroup {
+                    id,
+                    title: t.clone(),
+                    username: u.clone(),
+                };
+                if vis.can_see(&group) {
+                    (escape(t), u.as_deref().map(escape).unwrap_or_default())
+                } else {
+                    ("(无权查看)".to_string(), String::new())
+                }
+            }
+            None => ("(未加入)".to_string(), String::new()),
+        };
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td>",
+            id, title, username
+        ));
+        if edit_groups {
+            html.push_str(&format!("<td>{}</td>", remove_button("/groups/remove", id)));
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+    if edit_groups {
+        html.push_str(&id_form("/groups/add", "添加群"));
+    }
+
+    let mut admins = config
+        .role_holders()
+        .into_iter()
+        .filter_map(|id| Some((config.role(id)?, id)))
+        .collect::<Vec<_>>();
+    admins.sort_by_key(|a| Reverse(*a));
+    html.push_str("<h3>管理员</h3><table>");
+    for (role, id) in admins {
+        html.push_str(&format!("<tr><td>{}</td><td>{}</td>", id, role.name()));
+        if config.can_assign(user, id, None) {
+            html.push_str(&format!("<td>{}</td>", remove_button("/admins/remove", id)));
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+    if config.can(user, Capability::AddAdmins) {
+        html.push_str(&id_form("/admins/add", "添加管理员"));
+    }
+
+    page("群组与管理员", &html, config.is_owner(user))
+}
+
+fn history(req: &Request, vis: &Visibility, owner: bool) -> Response {
+    let history = History::read();
+    let Some(user) = req.query.get("user").and_then(|u| u.parse::<i64>().ok()) else {
+        let mut targets = history
+            .targets
+            .iter()
+            .filter_map(|(user, s)| Some((*user, s.last()?.last_seen)))
+            .collect::<Vec<_>>();
+        targets.sort_by_key(|t| Reverse(t.1));
+        let rows = targets
+            .iter()
+            .take(PANEL_SHOW)
+            .map(|(user, seen)| {
+                format!(
+                    "<tr><td><a href=\"/history?user={0}\">{0}</a></td><td>{1}</td></tr>",
+                    user,
+                    seen.format("%Y-%m-%d %H:%M")
+                )
+            })
+            .collect::<String>();
+        return page(
+            "查询历史",
+            &format!(
+                "<table><tr><th>用户</th><th>最近查询</th></tr>{}</table>",
+                rows
+            ),
+            owner,
+        );
+    };
+
+    let rows = history
+        .targets
+        .get(&user)
+        .into_iter()
+        .flatten()
+        .rev()
+        .map(|s| {
+            let visible = vis.filter(&s.groups);
+            let mut groups = visible
+                .iter()
+                .map(|g| format!("{} ({})", escape(&g.title), g.id))
+                .collect::<Vec<_>>();
+            if visible.len() < s.groups.len() {
+                groups.push(format!("另有 {} 个群", s.groups.len() - visible.len()));
+            }
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                s.first_seen.format("%Y-%m-%d %H:%M"),
+                s.last_seen.format("%Y-%m-%d %H:%M"),
+                groups.join("<br>")
+            )
+        })
+        .collect::<String>();
+    page(
+        &format!("查询历史: {}", user),
+        &format!(
+            "<table><tr><th>首次</th><th>最近</th><th>共同群</th></tr>{}</table>",
+            rows
+        ),
+        owner,
+    )
+}
+
+fn audit(req: &Request) -> Response {
+    let user = req.query.get("user").and_then(|u| u.parse::<i64>().ok());
+    let since = match req.query.get("since").filter(|s| !s.is_empty()) {
+        Some(s) => match parse_since(s) {
+            Some(t) => Some(t),
+            None => return Response::text(400, "起始时间格式: 7d / 12h / 30m / YYYY-MM-DD"),
+        },
+        None => None,
+    };
+    let entries = search(user, since);
+    let rows = entries
+        .iter()
+        .rev()
+        .take(PANEL_SHOW)
+        .map(|e| format!("<tr><td>{}</td></tr>", escape(&e.format())))
+        .collect::<String>();
+
+    page(
+        "审计日志",
+        &format!(
+            "<form>请求者/目标 <input name=\"user\" value=\"{}\"> 起始 <input name=\"since\" \
+             placeholder=\"7d / 2025-01-31\" value=\"{}\"> <button>搜索</button></form>\
+             <p>共 {} 条, 显示最近 {} 条</p><table>{}</table>",
+            user.map(|u| u.to_string()).unwrap_or_default(),
+            escape(
+                req.query
+                    .get("since")
+                    .map(|s| s.as_str())
+                    .unwrap_or_default()
+            ),
+            entries.len(),
+            PANEL_SHOW.min(entries.len()),
+            rows
+        ),
+        true,
+    )
+}
+
+/// Adds or removes a group or admin, as far as `user`'s role allows; admins are added as moderators.
+fn modify(req: &Request, user: i64) -> Response {
+    let Some(id) = req
+        .form()
+        .get("id")
+        .and_then(|i| i.trim().parse::<i64>().ok())
+    else {
+        return Response::text(400, "id解析失败");
+    };
+
+    let started = Instant::now();
+    let mut config = MainConfig::read_config();
+    let allowed = match req.path.as_str() {
+        "/groups/add" | "/groups/remove" => config.can(user, Capability::AddGroups),
+        "/admins/add" => config.can_assign(user, id, Some(Role::Moderator)),
+        "/admins/remove" => config.can_assign(user, id, None),
+        _ => return Response::text(404, "not found"),
+    };
+    if !allowed {
+        return Response::text(403, "权限不足");
+    }
+    let command = match req.path.as_str() {
+        "/groups/add" => config.groups.insert(id).then_some("/addgroup"),
+        "/groups/remove" => config.groups.remove(&id).then_some("/delgroup"),
+        "/admins/add" => config.admins.insert(id).then_some("/addadmin"),
+        _ => config.role(id).is_some().then(|| {
+            config.assign(id, None);
+            "/revoke"
+        }),
+    };
+    if let Some(command) = command {
+        MainConfig::rewrite_config(Some(config));
+        record(AuditEntry::new(user, 0, command, started).target(id));
+    }
+
+    Response::redirect("/")
+}
+
+async fn handle(pool: &Pool, req: Request) -> Response {
+    if req.path == "/login" {
+        return match req.query.get("token").and_then(|t| take(&LINKS, t, true)) {
+            Some(user) => {
+                let Ok(session) = random_token() else {
+                    return Response::text(500, "internal error");
+                };
+                SESSIONS.lock().unwrap().insert(
+                    session.clone(),
+                    (
+                        user,
+                        Instant::now() + Duration::from_secs(PANEL_SESSION_TTL),
+                    ),
+                );
+                Response::redirect("/").header(
+                    "Set-Cookie",
+                    format!(
+                        "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+                        COOKIE, session, PANEL_SESSION_TTL
+                    ),
+                )
+            }
+            None => Response::text(
+                401,
+                "登录链接无效或已过期, 请在 Bot 私聊中发送 /panel 重新获取",
+            ),
+        };
+    }
+
+    let session = req.cookie(COOKIE).unwrap_or_default().to_string();
+    let config = MainConfig::read_config();
+    // Losing admin rights ends the session on the next request.
+    let Some(user) = take(&SESSIONS, &session, false).filter(|u| config.is_admin(*u)) else {
+        return Response::text(401, "请在 Bot 私聊中发送 /panel 获取登录链接");
+    };
+    let vis = config.visibility(user, user, true);
+
+    match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/") => index(pool, &config, user, &vis).await,
+        ("GET", "/history") => history(&req, &vis, config.is_owner(user)),
+        ("GET", "/audit") if config.is_owner(user) => audit(&req),
+        ("GET", "/audit") => Response::text(403, "仅所有者可查看审计日志"),
+        ("GET", "/logout") => {
+            SESSIONS.lock().unwrap().remove(&session);
+            Response::text(200, "已退出")
+                .header("Set-Cookie", format!("{}=; Path=/; Max-Age=0", COOKIE))
+        }
+        ("POST", _) => modify(&req, user),
+        _ => Response::text(404, "not found"),
+    }
+}
+
+pub async fn panel_task(addr: String, pool: Pool) -> Result<()> {
+    serve("panel", addr, move |req| {
+        let pool = pool.clone();
+        async move { handle(&pool, req).await }
+    })
+    .await
+}
@@ -0,0 +1,97 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{check::CommonGroup, config::MainConfig, roles::Capability};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetailLevel {
+    #[default]
+    Count,
+    Names,
+    Links,
+}
+
+impl DetailLevel {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "count" => Some(Self::Count),
+            "names" => Some(Self::Names),
+            "links" => Some(Self::Links),
+            _ => None,
+        }
+    }
+}
+
+/// Detail level forced for every query made in one chat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatLevel {
+    pub chat: i64,
+    pub level: DetailLevel,
+}
+
+/// Groups whose names one admin may see; admins without a scope see all groups.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminScope {
+    pub admin: i64,
+    pub groups: HashSet<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Visibility {
+    pub level: DetailLevel,
+    pub scope: Option<HashSet<i64>>,
+    pub private_chat: bool,
+}
+
+impl Visibility {
+    pub fn detailed(&self) -> bool {
+        self.level >= DetailLevel::Names
+    }
+
+    /// Private (no-username) groups are never named outside private chats.
+    pub fn can_see(&self, group: &CommonGroup) -> bool {
+        self.detailed()
+            && self.scope.as_ref().is_none_or(|s| s.contains(&group.id))
+            && (self.private_chat || group.username.is_some())
+    }
+
+    pub fn filter(&self, list: &[CommonGroup]) -> Vec<CommonGroup> {
+        list.iter().filter(|g| self.can_see(g)).cloned().collect()
+    }
+
+    pub fn describe(&self, group: &CommonGroup) -> String {
+        if self.level < DetailLevel::Links {
+            return format!("{} - {}", group.title, group.display_username());
+        }
+        match &group.username {
+            Some(u) => format!("{} - https://t.me/{}", group.title, u),
+            None => format!("{} - https://t.me/c/{}", group.title, group.id),
+        }
+    }
+}
+
+impl MainConfig {
+    pub fn visibility(&self, requester: i64, chat_id: i64, private_chat: bool) -> Visibility {
+        let level = match self.chat_levels.iter().find(|c| c.chat == chat_id) {
+            Some(c) => c.level,
+            None if private_chat && self.can(requester, Capability::SeeNames) => DetailLevel::Names,
+            None => DetailLevel::Count,
+        };
+        let scope = if self.is_owner(requester) {
+            None
+        } else {
+            self.admin_scopes
+                .iter()
+                .find(|s| s.admin == requester)
+                .map(|s| s.groups.clone())
+        };
+
+        Visibility {
+            level,
+            scope,
+            private_chat,
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use grammers_client::{Client, InvocationError, types::PackedChat};
+use tokio::time::sleep;
+
+use crate::{
+    check::{ACCESS_HASH_ERROR, CommonGroup, common_groups, get_username},
+    config::MainConfig,
+    defs::{BANNED_COOLDOWN, COVERAGE_INTERVAL},
+    metrics,
+    utils::{get_packed_user, monitored_chats},
+};
+
+pub const NO_USERBOT_ERROR: &str = "没有可用的 UserBot";
+
+/// One userbot session. `coverage` is the set of monitored groups it is a
+/// member of; `None` until first refreshed, in which case it may cover anything.
+/// Access hashes are only valid for the account that saw them, so each keeps its own.
+pub struct Account {
+    pub session: String,
+    pub client: Client,
+    coverage: RwLock<Option<HashSet<i64>>>,
+    cooldown: Mutex<Option<Instant>>,
+    hashes: Mutex<HashMap<i64, PackedChat>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Account {
+    pub fn coverage(&self) -> Option<HashSet<i64>> {
+        self.coverage.read().unwrap().clone()
+    }
+
+    pub fn cooldown_left(&self) -> Option<Duration> {
+        let until = (*self.cooldown.lock().unwrap())?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    /// `(hits, misses)` of the access hash cache.
+    pub fn cache_stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    pub async fn packed_user(&self, user: i64) -> Result<PackedChat> {
+        if let Some(packed) = self.hashes.lock().unwrap().get(&user) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(*packed);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let packed = get_packed_user(&self.client, user).await?;
+        self.hashes.lock().unwrap().insert(user, packed);
+        Ok(packed)
+    }
+
+    fn covered(&self, groups: &HashSet<i64>) -> HashSet<i64> {
+        match self.coverage() {
+            Some(c) => c.intersection(groups).copied().collect(),
+            None => groups.clone(),
+        }
+    }
+
+    /// Puts the account aside after a flood wait or when it was banned/logged out.
+    fn penalize(&self, e: &anyhow::Error) {
+        let Some(e) = e.downcast_ref::<InvocationError>() else {
+            return;
+        };
+        let wait = if e.is("FLOOD_WAIT") {
+            metrics::flood_wait();
+            match e {
+                InvocationError::Rpc(rpc) => Duration::from_secs(rpc.value.unwrap_or(60) as u64),
+                _ => return,
+            }
+        } else if e.is("USER_DEACTIVATED*")
+            || e.is("AUTH_KEY_UNREGISTERED")
+            || e.is("SESSION_REVOKED")
+        {
+            Duration::from_secs(BANNED_COOLDOWN)
+        } else {
+            return;
+        };
+
+        log::warn!(
+            "userbot {} cooling down for {:?}: {}",
+            self.session,
+            wait,
+            e
+        );
+        *self.cooldown.lock().unwrap() = Some(Instant::now() + wait);
+    }
+}
+
+/// The matched groups of one user, and the monitored groups nobody was asked about.
+#[derive(Debug, Clone, Default)]
+pub struct Lookup {
+    pub groups: Vec<CommonGroup>,
+    /// Covered only by accounts that failed or are cooling down, so the result is incomplete.
+    pub missed: HashSet<i64>,
+    /// Covered by no account at all.
+    pub uncovered: HashSet<i64>,
+}
+
+impl Lookup {
+    /// Groups whose membership is unknown, which history keeps at their last known state.
+    pub fn unqueried(&self) -> HashSet<i64> {
+        self.missed.union(&self.uncovered).copied().collect()
+    }
+
+    /// A note for results missing some groups, `None` when every covered group answered.
+    pub fn incomplete_note(&self) -> Option<String> {
+        (!self.missed.is_empty()).then(|| {
+            format!(
+                "注意: {} 个监控群暂时无法查询, 结果可能不完整",
+                self.missed.len()
+            )
+        })
+    }
+}
+
+/// All userbot accounts; lookups rotate over the ones not cooling down.
+#[derive(Clone)]
+pub struct Pool {
+    accounts: Arc<Vec<Account>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Pool {
+    pub fn new(accounts: Vec<(String, Client)>) -> Self {
+        Self {
+            accounts: Arc::new(
+                accounts
+                    .into_iter()
+                    .map(|(session, client)| Account {
+                        session,
+                        client,
+                        coverage: RwLock::new(None),
+                        cooldown: Mutex::new(None),
+                        hashes: Mutex::new(HashMap::new()),
+                        hits: AtomicU64::new(0),
+                        misses: AtomicU64::new(0),
+                    })
+                    .collect(),
+            ),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn accounts(&self) -> &[Account] {
+        &self.accounts
+    }
+
+    /// Available accounts, starting from a different one on every call.
+    fn available(&self) -> Vec<&Account> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.accounts.len();
+
+        (0..len)
+            .map(|i| &self.accounts[(start + i) % len])
+            .filter(|a| a.cooldown_left().is_none())
+            .collect()
+    }
+
+    /// Whether `session` is the account that should react to events in `group`,
+    /// so members of several userbots' groups aren't reported once per account.
+    pub fn handles(&self, session: &str, group: i64) -> bool {
+        self.accounts
+            .iter()
+            .find(|a| a.coverage().is_none_or(|c| c.contains(&group)))
+            .is_some_and(|a| a.session == session)
+    }
+
+    pub async fn refresh_coverage(&self, groups: &HashSet<i64>) {
+        for account in self.accounts.iter() {
+            match monitored_chats(&account.client, groups).await {
+                Ok(chats) => {
+                    *account.coverage.write().unwrap() =
+                        Some(chats.iter().map(|c| c.id()).collect());
+                }
+                Err(e) => log::warn!("refresh coverage of {} failed: {}", account.session, e),
+            }
+        }
+    }
+
+    /// Monitored groups no account is a member of, once every account's coverage is known.
+    pub fn uncovered(&self, groups: &HashSet<i64>) -> Option<Vec<i64>> {
+        let mut missing = groups.clone();
+        for account in self.accounts.iter() {
+            let coverage = account.coverage()?;
+            missing.retain(|g| !coverage.contains(g));
+        }
+
+        let mut missing = missing.into_iter().collect::<Vec<_>>();
+        missing.sort();
+        Some(missing)
+    }
+
+    pub async fn is_self(&self, bot: &Client, id: i64) -> Result<bool> {
+        for account in self.accounts.iter() {
+            if account.client.get_me().await?.id() == id {
+                return Ok(true);
+            }
+        }
+
+        Ok(id == bot.get_me().await?.id())
+    }
+
+    /// Turns `@username` or a numeric id into a user id.
+    pub async fn resolve(&self, target: &str) -> Result<i64> {
+        if !target.starts_with("@") {
+            return target
+                .parse::<i64>()
+                .map_err(|_| anyhow::anyhow!("id解析失败"));
+        }
+
+        let username = target.trim_start_matches("@").trim();
+        let mut last_err = anyhow::anyhow!(NO_USERBOT_ERROR);
+        for account in self.available() {
+            match account.client.resolve_username(username).await {
+                Ok(Some(chat)) => return Ok(chat.id()),
+                Ok(None) => return Err(anyhow::anyhow!("未知用户")),
+                Err(e) => {
+                    let e = anyhow::Error::from(e);
+                    account.penalize(&e);
+                    last_err = e;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+
+    pub async fn get_username(&self, user: i64) -> Option<String> {
+        for account in self.available() {
+            if let Ok(packed) = account.packed_user(user).await {
+                return get_username(&account.client, &packed).await;
+            }
+        }
+
+        None
+    }
+
+    /// Merges the matched groups of every account, each asked only about the
+    /// groups it covers that no earlier account has answered for.
+    pub async fn common_groups(&self, user: i64, groups: &HashSet<i64>) -> Result<Lookup> {
+        let accounts = self.available();
+        if groups.is_empty() {
+            return Ok(Lookup::default());
+        }
+        if accounts.is_empty() {
+            return Err(anyhow::anyhow!(NO_USERBOT_ERROR));
+        }
+        let mut remaining = groups.clone();
+        let mut found: Vec<CommonGroup> = Vec::new();
+        let mut answered = false;
+        let mut last_err = None;
+
+        for account in accounts {
+            let covered = account.covered(&remaining);
+            if covered.is_empty() {
+                continue;
+            }
+            let packed = match account.packed_user(user).await {
+                Ok(p) => p,
+                Err(e) => {
+                    account.penalize(&e);
+                    last_err = Some(if e.is::<InvocationError>() {
+                        e
+                    } else {
+                        anyhow::anyhow!(ACCESS_HASH_ERROR)
+                    });
+                    continue;
+                }
+            };
+            match common_groups(&account.client, &packed, &covered).await {
+                Ok(list) => {
+                    answered = true;
+                    for g in list {
+                        if !found.iter().any(|f| f.id == g.id) {
+                            found.push(g);
+                        }
+                    }
+                    remaining.retain(|g| !covered.contains(g));
+                }
+                Err(e) => {
+                    account.penalize(&e);
+                    last_err = Some(e);
+                }
+            }
+            if remaining.is_empty() {
+                break;
+            }
+        }
+
+        // Accounts covering none of the groups aren't asked, so only a failed one is an error.
+        if !answered && let Some(e) = last_err {
+            return Err(e);
+        }
+        let (missed, uncovered): (HashSet<i64>, HashSet<i64>) =
+            remaining.into_iter().partition(|g| {
+                self.accounts
+                    .iter()
+                    .any(|a| a.coverage().is_none_or(|c| c.contains(g)))
+            });
+        if !missed.is_empty() || !uncovered.is_empty() {
+            log::warn!(
+                "check {}: {} monitored groups unanswered, {} not covered by any userbot",
+                user,
+                missed.len(),
+                uncovered.len()
+            );
+        }
+
+        Ok(Lookup {
+            groups: found,
+            missed,
+            uncovered,
+        })
+    }
+}
+
+pub async fn coverage_task(pool: Pool) -> Result<()> {
+    loop {
+        pool.refresh_coverage(&MainConfig::read_config().all_groups())
+            .await;
+        sleep(Duration::from_secs(COVERAGE_INTERVAL)).await;
+    }
+}
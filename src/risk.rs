@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupRule {
+    pub id: i64,
+    pub weight: Option<f64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Extra weight added once per matched group carrying the tag; `flag` marks
+/// any membership in such a group regardless of the score.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagRule {
+    pub name: String,
+    #[serde(default)]
+    pub weight: f64,
+    #[serde(default)]
+    pub flag: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Verdict {
+    pub min_score: f64,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskRules {
+    #[serde(default = "default_weight")]
+    pub default_weight: f64,
+    /// Score from which a `risk` webhook is sent; flagged tags always send one.
+    pub alert_score: Option<f64>,
+    #[serde(default)]
+    pub groups: Vec<GroupRule>,
+    #[serde(default)]
+    pub tags: Vec<TagRule>,
+    #[serde(default)]
+    pub verdicts: Vec<Verdict>,
+}
+
+impl Default for RiskRules {
+    fn default() -> Self {
+        Self {
+            default_weight: default_weight(),
+            alert_score: None,
+            groups: Vec::new(),
+            tags: Vec::new(),
+            verdicts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Assessment {
+    pub score: f64,
+    pub verdict: Option<String>,
+    pub flags: Vec<String>,
+}
+
+impl Assessment {
+    pub fn format(&self) -> String {
+        let mut s = format!("风险评分: {:.1}", self.score);
+        if let Some(v) = &self.verdict {
+            s.push_str(&format!(" ({})", v));
+        }
+        if !self.flags.is_empty() {
+            s.push_str(&format!("\n标记: {}", self.flags.join(", ")));
+        }
+        s
+    }
+}
+
+impl RiskRules {
+    pub fn enabled(&self) -> bool {
+        !self.groups.is_empty() || !self.tags.is_empty() || !self.verdicts.is_empty()
+    }
+
+    /// Scores a set of matched monitored group ids. The verdict is the one with
+    /// the highest `min_score` not above the score.
+    pub fn assess(&self, groups: &[i64]) -> Assessment {
+        let mut score = 0.0;
+        let mut flags = Vec::new();
+
+        for id in groups {
+            let rule = self.groups.iter().find(|r| r.id == *id);
+            score += rule.and_then(|r| r.weight).unwrap_or(self.default_weight);
+
+            for tag in rule.map(|r| r.tags.as_slice()).unwrap_or_default() {
+                let Some(t) = self.tags.iter().find(|t| &t.name == tag) else {
+                    continue;
+                };
+                score += t.weight;
+                if t.flag && !flags.contains(&t.name) {
+                    flags.push(t.name.clone());
+                }
+            }
+        }
+
+        let verdict = self
+            .verdicts
+            .iter()
+            .filter(|v| v.min_score <= score)
+            .max_by(|a, b| a.min_score.total_cmp(&b.min_score))
+            .map(|v| v.label.clone());
+
+        Assessment {
+            score,
+            verdict,
+            flags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> RiskRules {
+        RiskRules {
+            default_weight: 1.0,
+            alert_score: None,
+            groups: vec![
+                GroupRule {
+                    id: 1,
+                    weight: Some(5.0),
+                    tags: vec!["scam".to_string()],
+                },
+                GroupRule {
+                    id: 2,
+                    weight: None,
+                    tags: vec!["scam".to_string(), "spam".to_string()],
+                },
+            ],
+            tags: vec![
+                TagRule {
+                    name: "scam".to_string(),
+                    weight: 2.0,
+                    flag: true,
+                },
+                TagRule {
+                    name: "spam".to_string(),
+                    weight: 0.5,
+                    flag: false,
+                },
+            ],
+            verdicts: vec![
+                Verdict {
+                    min_score: 0.0,
+                    label: "low".to_string(),
+                },
+                Verdict {
+                    min_score: 8.0,
+                    label: "high".to_string(),
+                },
+                Verdict {
+                    min_score: 4.0,
+                    label: "medium".to_string(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn group_weight_overrides_default() {
+        let rules = RiskRules {
+            tags: Vec::new(),
+            ..rules()
+        };
+        assert_eq!(rules.assess(&[3]).score, 1.0);
+        assert_eq!(rules.assess(&[1]).score, 5.0);
+        assert_eq!(rules.assess(&[1, 3, 4]).score, 7.0);
+    }
+
+    #[test]
+    fn tags_add_weight_and_flags() {
+        let a = rules().assess(&[1, 2]);
+        // 5 + 2 (scam) for group 1, 1 + 2 (scam) + 0.5 (spam) for group 2.
+        assert_eq!(a.score, 10.5);
+        assert_eq!(a.flags, vec!["scam".to_string()]);
+
+        let a = rules().assess(&[3]);
+        assert!(a.flags.is_empty());
+    }
+
+    #[test]
+    fn highest_reached_verdict_wins() {
+        let rules = rules();
+        assert_eq!(rules.assess(&[]).verdict.as_deref(), Some("low"));
+        assert_eq!(rules.assess(&[1]).verdict.as_deref(), Some("medium"));
+        assert_eq!(rules.assess(&[1, 2]).verdict.as_deref(), Some("high"));
+
+        let rules = RiskRules {
+            verdicts: vec![Verdict {
+                min_score: 1.0,
+                label: "any".to_string(),
+            }],
+            ..rules
+        };
+        assert_eq!(rules.assess(&[]).verdict, None);
+    }
+
+    #[test]
+    fn enabled_needs_some_rule() {
+        assert!(!RiskRules::default().enabled());
+        assert!(rules().enabled());
+        assert!(
+            RiskRules {
+                verdicts: Vec::new(),
+                tags: Vec::new(),
+                ..rules()
+            }
+            .enabled()
+        );
+        assert!(
+            !RiskRules {
+                default_weight: 3.0,
+                ..Default::default()
+            }
+            .enabled()
+        );
+    }
+}
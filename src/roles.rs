@@ -0,0 +1,219 @@
+use std::collections::{BTreeSet, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::MainConfig, defs::SUPER_ADMIN};
+
+/// Roles from least to most trusted. Members of the legacy `admins` set are moderators,
+/// and `SUPER_ADMIN` is always an owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Moderator,
+    Manager,
+    Owner,
+}
+
+impl Role {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "owner" => Some(Self::Owner),
+            "manager" => Some(Self::Manager),
+            "moderator" => Some(Self::Moderator),
+            "viewer" => Some(Self::Viewer),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Manager => "manager",
+            Self::Moderator => "moderator",
+            Self::Viewer => "viewer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    AddGroups,
+    AddAdmins,
+    SeeNames,
+    Export,
+    BatchCheck,
+    Watch,
+    Ban,
+}
+
+/// A role granted to one user with `/grant`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleGrant {
+    pub user: i64,
+    pub role: Role,
+}
+
+/// What each role may do; owners can also grant roles, set levels and scopes, read the
+/// audit log and manage tenants, whatever is configured here.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capabilities {
+    #[serde(default = "owner_default")]
+    pub owner: BTreeSet<Capability>,
+    #[serde(default = "manager_default")]
+    pub manager: BTreeSet<Capability>,
+    #[serde(default = "moderator_default")]
+    pub moderator: BTreeSet<Capability>,
+    #[serde(default = "viewer_default")]
+    pub viewer: BTreeSet<Capability>,
+}
+
+fn owner_default() -> BTreeSet<Capability> {
+    use Capability::*;
+    BTreeSet::from([
+        AddGroups, AddAdmins, SeeNames, Export, BatchCheck, Watch, Ban,
+    ])
+}
+
+fn manager_default() -> BTreeSet<Capability> {
+    owner_default()
+}
+
+fn moderator_default() -> BTreeSet<Capability> {
+    use Capability::*;
+    BTreeSet::from([SeeNames, Export, BatchCheck, Watch, Ban])
+}
+
+fn viewer_default() -> BTreeSet<Capability> {
+    BTreeSet::from([Capability::SeeNames])
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            owner: owner_default(),
+            manager: manager_default(),
+            moderator: moderator_default(),
+            viewer: viewer_default(),
+        }
+    }
+}
+
+impl Capabilities {
+    fn of(&self, role: Role) -> &BTreeSet<Capability> {
+        match role {
+            Role::Owner => &self.owner,
+            Role::Manager => &self.manager,
+            Role::Moderator => &self.moderator,
+            Role::Viewer => &self.viewer,
+        }
+    }
+}
+
+impl MainConfig {
+    pub fn role(&self, id: i64) -> Option<Role> {
+        if id == SUPER_ADMIN {
+            return Some(Role::Owner);
+        }
+        match self.roles.iter().find(|r| r.user == id) {
+            Some(r) => Some(r.role),
+            None if self.admins.contains(&id) => Some(Role::Moderator),
+            None => None,
+        }
+    }
+
+    pub fn is_owner(&self, id: i64) -> bool {
+        self.role(id) == Some(Role::Owner)
+    }
+
+    pub fn can(&self, id: i64, capability: Capability) -> bool {
+        self.role(id)
+            .is_some_and(|r| self.capabilities.of(r).contains(&capability))
+    }
+
+    /// Everyone holding a role, who receives admin alerts.
+    pub fn role_holders(&self) -> HashSet<i64> {
+        let mut users = self.admins.clone();
+        users.extend(self.roles.iter().map(|r| r.user));
+        users.insert(SUPER_ADMIN);
+        users
+    }
+
+    /// Whether `requester` may give `target` the role `role`, or take its role away when `None`:
+    /// owners manage anyone but `SUPER_ADMIN`, others need `add_admins` and only manage roles below their own.
+    pub fn can_assign(&self, requester: i64, target: i64, role: Option<Role>) -> bool {
+        let Some(own) = self.role(requester) else {
+            return false;
+        };
+        if target == SUPER_ADMIN {
+            return false;
+        }
+        if own == Role::Owner {
+            return true;
+        }
+        self.can(requester, Capability::AddAdmins)
+            && self.role(target).is_none_or(|r| r < own)
+            && role.is_none_or(|r| r < own)
+    }
+
+    /// Gives `user` `role`, or removes every role it holds when `None`.
+    pub fn assign(&mut self, user: i64, role: Option<Role>) {
+        self.roles.retain(|r| r.user != user);
+        self.admins.remove(&user);
+        if let Some(role) = role {
+            self.roles.push(RoleGrant { user, role });
+        }
+    }
+
+    pub fn format_roles(&self) -> String {
+        let mut users = self
+            .role_holders()
+            .into_iter()
+            .filter_map(|u| Some((self.role(u)?, u)))
+            .collect::<Vec<_>>();
+        users.sort_by(|a, b| b.cmp(a));
+        users
+            .iter()
+            .map(|(role, user)| format!("{} - {}", user, role.name()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MainConfig {
+        let mut config = MainConfig::default();
+        config.assign(10, Some(Role::Owner));
+        config.assign(20, Some(Role::Manager));
+        config.assign(30, Some(Role::Moderator));
+        config.admins.insert(40);
+        config
+    }
+
+    #[test]
+    fn owners_assign_anyone_but_super_admin() {
+        let config = config();
+        assert!(config.can_assign(10, 20, Some(Role::Owner)));
+        assert!(config.can_assign(SUPER_ADMIN, 10, None));
+        assert!(!config.can_assign(10, SUPER_ADMIN, None));
+    }
+
+    #[test]
+    fn others_assign_only_below_their_role() {
+        let mut config = config();
+        assert_eq!(config.role(40), Some(Role::Moderator));
+        assert!(config.can_assign(20, 30, Some(Role::Viewer)));
+        assert!(config.can_assign(20, 99, Some(Role::Moderator)));
+        assert!(!config.can_assign(20, 30, Some(Role::Manager)));
+        assert!(!config.can_assign(20, 10, None));
+        assert!(!config.can_assign(30, 99, Some(Role::Viewer)));
+        assert!(!config.can_assign(99, 30, None));
+
+        config.capabilities.manager.remove(&Capability::AddAdmins);
+        assert!(!config.can_assign(20, 30, None));
+    }
+}
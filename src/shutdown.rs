@@ -0,0 +1,105 @@
+use std::{
+    env,
+    fs::File,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use grammers_client::Client;
+use tokio::time::sleep;
+
+use crate::{
+    defs::{AUDIT_FILE, HISTORY_FILE, SHUTDOWN_TIMEOUT},
+    pool::Pool,
+    utils::save_session,
+};
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Marks a query as running until dropped, so shutdown can wait for it.
+pub struct InFlight;
+
+impl InFlight {
+    pub fn start() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM from systemd or Docker.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+                return;
+            }
+            Err(e) => log::warn!("listen for SIGTERM failed: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("listen for Ctrl-C failed: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Forces files written without an explicit sync out to disk.
+fn sync(path: &str) {
+    if let Ok(file) = File::open(path)
+        && let Err(e) = file.sync_all()
+    {
+        log::warn!("sync {} failed: {}", path, e);
+    }
+}
+
+/// Waits up to `SHUTDOWN_TIMEOUT` seconds for running queries, then saves sessions and syncs logs.
+pub async fn finish(pool: &Pool, bot: &Client) {
+    log::info!("Shutting down...");
+    let started = Instant::now();
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 {
+        if started.elapsed() >= Duration::from_secs(SHUTDOWN_TIMEOUT) {
+            log::warn!(
+                "shutdown: giving up on {} running queries",
+                IN_FLIGHT.load(Ordering::SeqCst)
+            );
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    save_session(pool, bot);
+    sync(AUDIT_FILE);
+    sync(HISTORY_FILE);
+    log::info!("Bye!");
+}
+
+/// Replaces the process with a fresh copy of itself, so every client connects anew.
+pub fn restart() -> Result<()> {
+    let mut command = Command::new(env::current_exe()?);
+    command.args(env::args_os().skip(1));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        Err(command.exec().into())
+    }
+    #[cfg(not(unix))]
+    {
+        command.spawn()?;
+        std::process::exit(0)
+    }
+}
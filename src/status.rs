@@ -0,0 +1,97 @@
+use std::{sync::OnceLock, time::Instant};
+
+use chrono::{Local, Utc};
+
+use crate::{audit::search, config::MainConfig, health::format_health, pool::Pool};
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+pub fn mark_started() {
+    STARTED.get_or_init(Instant::now);
+}
+
+fn format_uptime() -> String {
+    let secs = STARTED.get().map(|t| t.elapsed().as_secs()).unwrap_or(0);
+    let (days, hours, minutes) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{}天{}小时{}分钟", days, hours, minutes)
+    } else {
+        format!("{}小时{}分钟", hours, minutes)
+    }
+}
+
+/// Queries answered since local midnight, counted from the audit log so restarts don't reset it.
+fn queries_today() -> usize {
+    let Some(midnight) = Local::now()
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .and_then(|t| t.and_local_timezone(Local).earliest())
+    else {
+        return 0;
+    };
+
+    search(None, Some(midnight.with_timezone(&Utc)))
+        .iter()
+        .filter(|e| e.command.starts_with("/check") || e.command == "/reply")
+        .map(|e| match e.command.as_str() {
+            "/check batch" => e.count.unwrap_or(0),
+            _ => 1,
+        })
+        .sum()
+}
+
+pub fn format_status(pool: &Pool, config: &MainConfig) -> String {
+    let mut lines = vec![
+        format!("运行时间: {}", format_uptime()),
+        format!("连接状态:\n{}", format_health()),
+    ];
+
+    let all = config.all_groups();
+    let mut groups = format!("监控群: {} 个", all.len());
+    match pool.uncovered(&all) {
+        Some(missing) if !missing.is_empty() => groups.push_str(&format!(
+            ", 未加入: {}",
+            missing
+                .iter()
+                .map(|g| g.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        Some(_) => {}
+        None => groups.push_str(", 成员状态尚未刷新"),
+    }
+    lines.push(groups);
+    lines.push(format!("今日查询: {}", queries_today()));
+
+    let (hits, misses) = pool
+        .accounts()
+        .iter()
+        .map(|a| a.cache_stats())
+        .fold((0, 0), |(h, m), (a, b)| (h + a, m + b));
+    if hits + misses > 0 {
+        lines.push(format!(
+            "access_hash 缓存命中率: {:.1}% ({}/{})",
+            hits as f64 * 100.0 / (hits + misses) as f64,
+            hits,
+            hits + misses
+        ));
+    } else {
+        lines.push("access_hash 缓存命中率: -".to_string());
+    }
+
+    let cooling = pool
+        .accounts()
+        .iter()
+        .filter_map(|a| {
+            a.cooldown_left()
+                .map(|d| format!("{} 剩余 {} 秒", a.session, d.as_secs()))
+        })
+        .collect::<Vec<_>>();
+    lines.push(if cooling.is_empty() {
+        "限流: 无".to_string()
+    } else {
+        format!("限流: {}", cooling.join(", "))
+    });
+
+    lines.join("\n")
+}
@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::MainConfig,
+    policy::Visibility,
+    roles::{Capability, Role, RoleGrant},
+};
+
+/// A chat running the bot with its own monitored groups and admins, instead of the global ones.
+/// Its admins may only add the `approved` groups, which are the ones an owner added before.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Tenant {
+    pub chat: i64,
+    #[serde(default)]
+    pub groups: HashSet<i64>,
+    #[serde(default)]
+    pub admins: HashSet<i64>,
+    #[serde(default)]
+    pub approved: HashSet<i64>,
+}
+
+impl Tenant {
+    pub fn format(&self) -> String {
+        let sorted = |set: &HashSet<i64>| {
+            let mut list = set.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+            list.sort();
+            if list.is_empty() {
+                "无".to_string()
+            } else {
+                list.join(", ")
+            }
+        };
+        format!(
+            "租户 {}\n监控群: {}\n已批准的群: {}\n管理员: {}",
+            self.chat,
+            sorted(&self.groups),
+            sorted(&self.approved),
+            sorted(&self.admins)
+        )
+    }
+}
+
+impl MainConfig {
+    pub fn tenant(&self, chat: i64) -> Option<&Tenant> {
+        self.tenants.iter().find(|t| t.chat == chat)
+    }
+
+    pub fn tenant_mut(&mut self, chat: i64) -> Option<&mut Tenant> {
+        self.tenants.iter_mut().find(|t| t.chat == chat)
+    }
+
+    /// The config as seen from `tenant`'s chat: its groups replace the global ones, its
+    /// admins are managers there, only owners keep their global role, and the watchlist,
+    /// which alerts the global admins, is left out.
+    pub fn for_tenant(&self, tenant: &Tenant) -> Self {
+        Self {
+            groups: tenant.groups.clone(),
+            admins: HashSet::new(),
+            roles: self
+                .roles
+                .iter()
+                .filter(|r| r.role == Role::Owner)
+                .cloned()
+                .chain(tenant.admins.iter().map(|&user| RoleGrant {
+                    user,
+                    role: Role::Manager,
+                }))
+                .collect(),
+            watchlist: HashSet::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Every group some chat monitors, which the userbots have to cover.
+    pub fn all_groups(&self) -> HashSet<i64> {
+        let mut groups = self.groups.clone();
+        for tenant in &self.tenants {
+            groups.extend(&tenant.groups);
+        }
+        groups
+    }
+
+    /// Whether `group` is monitored globally or by a tenant other than `chat`, so only
+    /// an owner may add it to `chat` without one tenant seeing another's members.
+    pub fn claimed_elsewhere(&self, group: i64, chat: i64) -> bool {
+        self.groups.contains(&group)
+            || self
+                .tenants
+                .iter()
+                .any(|t| t.chat != chat && t.groups.contains(&group))
+    }
+}
+
+impl Visibility {
+    /// Never names groups outside `groups`, whatever the admin's own scope allows.
+    pub fn restrict(&mut self, groups: &HashSet<i64>) {
+        self.scope = Some(match self.scope.take() {
+            Some(scope) => scope.intersection(groups).copied().collect(),
+            None => groups.clone(),
+        });
+    }
+}
+
+/// Applies a tenant admin's `/addgroup`, `/delgroup`, `/addadmin` or `/deladmin` to `chat`,
+/// returning the reply and whether the config changed. Groups need `add_groups` and admins
+/// `add_admins`, and only owners may add a group the tenant wasn't approved for.
+pub fn modify(
+    config: &mut MainConfig,
+    chat: i64,
+    requester: i64,
+    command: &str,
+    id: i64,
+) -> (&'static str, bool) {
+    let Some(tenant) = config.tenant(chat) else {
+        return ("当前群未启用租户", false);
+    };
+    let view = config.for_tenant(tenant);
+    let capability = match command {
+        "/addgroup" | "/delgroup" => Capability::AddGroups,
+        _ => Capability::AddAdmins,
+    };
+    if !view.can(requester, capability) {
+        return ("权限不足", false);
+    }
+    let owner = view.is_owner(requester);
+    if command == "/addgroup" && !owner {
+        if !tenant.approved.contains(&id) {
+            return ("该群未经所有者批准, 请联系所有者", false);
+        }
+        if config.claimed_elsewhere(id, chat) {
+            return ("该群已被其他租户或全局配置监控, 请联系所有者", false);
+        }
+    }
+
+    let Some(tenant) = config.tenant_mut(chat) else {
+        return ("当前群未启用租户", false);
+    };
+    let changed = match command {
+        "/addgroup" => {
+            let approved = owner && tenant.approved.insert(id);
+            tenant.groups.insert(id) || approved
+        }
+        "/delgroup" => tenant.groups.remove(&id),
+        "/addadmin" => tenant.admins.insert(id),
+        "/deladmin" => tenant.admins.remove(&id),
+        _ => false,
+    };
+
+    (if changed { "已更新" } else { "无变化" }, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: i64 = 100;
+    const OWNER: i64 = 10;
+    const ADMIN: i64 = 20;
+
+    fn config() -> MainConfig {
+        let mut config = MainConfig::default();
+        config.assign(OWNER, Some(Role::Owner));
+        config.groups.insert(1);
+        config.tenants = vec![
+            Tenant {
+                chat: CHAT,
+                admins: HashSet::from([ADMIN]),
+                approved: HashSet::from([1, 2, 3]),
+                ..Default::default()
+            },
+            Tenant {
+                chat: 200,
+                groups: HashSet::from([3]),
+                ..Default::default()
+            },
+        ];
+        config
+    }
+
+    #[test]
+    fn admins_add_only_approved_unclaimed_groups() {
+        let mut config = config();
+        assert_eq!(
+            modify(&mut config, CHAT, ADMIN, "/addgroup", 2),
+            ("已更新", true)
+        );
+        assert_eq!(
+            modify(&mut config, CHAT, ADMIN, "/addgroup", 2),
+            ("无变化", false)
+        );
+        assert!(!modify(&mut config, CHAT, ADMIN, "/addgroup", 4).1);
+        assert!(!modify(&mut config, CHAT, ADMIN, "/addgroup", 1).1);
+        assert!(!modify(&mut config, CHAT, ADMIN, "/addgroup", 3).1);
+        assert_eq!(config.tenant(CHAT).unwrap().groups, HashSet::from([2]));
+    }
+
+    #[test]
+    fn owners_add_and_approve_any_group() {
+        let mut config = config();
+        assert!(modify(&mut config, CHAT, OWNER, "/addgroup", 4).1);
+        let tenant = config.tenant(CHAT).unwrap();
+        assert!(tenant.groups.contains(&4) && tenant.approved.contains(&4));
+    }
+
+    #[test]
+    fn modify_checks_the_requester_and_chat() {
+        let mut config = config();
+        assert_eq!(
+            modify(&mut config, CHAT, 99, "/addadmin", 30),
+            ("权限不足", false)
+        );
+        assert_eq!(
+            modify(&mut config, 300, OWNER, "/addgroup", 1),
+            ("当前群未启用租户", false)
+        );
+        assert!(modify(&mut config, CHAT, ADMIN, "/addadmin", 30).1);
+        assert!(modify(&mut config, CHAT, ADMIN, "/deladmin", 30).1);
+        assert!(!config.tenant(CHAT).unwrap().admins.contains(&30));
+    }
+}
@@ -1,23 +1,22 @@
-use std::io::{self, BufRead, Write};
+use std::{
+    collections::HashSet,
+    io::Cursor,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
-use grammers_client::{Client, grammers_tl_types as tl, types::PackedChat};
-
-use crate::defs::{BOT_SESSION_FILE, SESSION_FILE};
-
-pub fn prompt(message: &str) -> Result<String> {
-    let stdout = io::stdout();
-    let mut stdout = stdout.lock();
-    stdout.write_all(message.as_bytes())?;
-    stdout.flush()?;
-
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
-
-    let mut line = String::new();
-    stdin.read_line(&mut line)?;
-    Ok(line.trim().to_string())
-}
+use grammers_client::{
+    Client, InputMessage, grammers_tl_types as tl,
+    types::{Chat, PackedChat},
+};
+use tokio::time::sleep;
+
+use crate::{
+    defs::{BOT_SESSION_FILE, COMMANDS, MESSAGE_MAX_LEN, SESSION_SAVE_INTERVAL},
+    metrics,
+    pool::Pool,
+    vault,
+};
 
 pub async fn get_common_chats(
     client: &Client,
@@ -26,6 +25,8 @@ pub async fn get_common_chats(
 ) -> Result<Vec<tl::enums::Chat>> {
     let mut chats = Vec::new();
     let mut max_id = 0_i64;
+    let started = Instant::now();
+    let mut pages = 0;
 
     loop {
         let req = tl::functions::messages::GetCommonChats {
@@ -44,21 +45,23 @@ pub async fn get_common_chats(
                     e,
                     user.access_hash
                 );
-                break;
+                return Err(e.into());
             }
         };
+        pages += 1;
         let slice = resp.chats();
         if slice.is_empty() {
-            continue;
+            break;
         }
         chats.extend(slice.clone());
-        if slice.clone().len() < per_page_limit {
+        if slice.len() < per_page_limit {
             break;
         }
         if let Some(last) = slice.last() {
             max_id = last.id();
         }
     }
+    metrics::common_chats(started.elapsed(), pages);
 
     Ok(chats)
 }
@@ -69,15 +72,23 @@ async fn get_access_hash(client: &Client, target_id: i64) -> Result<Option<i64>>
         access_hash: 0,
     });
 
-    let resp = client
+    let resp = match client
         .invoke(&tl::functions::users::GetUsers {
             id: vec![input_user],
         })
-        .await?;
+        .await
+    {
+        Ok(r) => r,
+        Err(e) => {
+            metrics::access_hash_failure("get_users");
+            return Err(e.into());
+        }
+    };
 
     if let Some(tl::enums::User::User(u)) = resp.into_iter().next() {
         return Ok(u.access_hash);
     }
+    metrics::access_hash_failure("get_users");
 
     let mut dialogs = client.iter_dialogs().limit(50);
     while let Some(d) = dialogs.next().await? {
@@ -85,14 +96,15 @@ async fn get_access_hash(client: &Client, target_id: i64) -> Result<Option<i64>>
             return Ok(d.chat.pack().access_hash);
         }
     }
+    metrics::access_hash_failure("dialogs");
 
-    return Ok(Some(0));
+    Ok(Some(0))
 }
 
 pub async fn get_packed_user(client: &Client, target_id: i64) -> Result<PackedChat> {
     #[allow(unused_assignments)]
     let mut hash = Some(0);
-    hash = get_access_hash(&client, target_id).await?;
+    hash = get_access_hash(client, target_id).await?;
 
     if matches!(hash, Some(0) | None) {
         log::error!(
@@ -111,14 +123,95 @@ pub async fn get_packed_user(client: &Client, target_id: i64) -> Result<PackedCh
     })
 }
 
-pub fn save_session(client: &Client, bot: &Client) {
-    match client.session().save_to_file(SESSION_FILE) {
-        Ok(_) => {}
-        Err(e) => {
-            log::error!("NOTE: failed to save the session, will sign out when done: {e}");
+/// The monitored groups as seen from `client`'s dialogs; groups it isn't a member of are missing.
+pub async fn monitored_chats(client: &Client, groups: &HashSet<i64>) -> Result<Vec<Chat>> {
+    let mut chats = Vec::new();
+    let mut dialogs = client.iter_dialogs();
+    while let Some(d) = dialogs.next().await? {
+        if groups.contains(&d.chat.id()) {
+            chats.push(d.chat);
+        }
+    }
+
+    Ok(chats)
+}
+
+/// The known command `text` starts with, e.g. `/check` for `/check@bot 123`.
+pub fn command_name(text: &str) -> Option<&'static str> {
+    let first = text.split_whitespace().next()?;
+    let name = first.split('@').next()?;
+    COMMANDS.iter().find(|c| **c == name).copied()
+}
+
+/// The arguments after `command`, without the `@bot` of `/check@bot`.
+pub fn command_args<'a>(text: &'a str, command: &str) -> &'a str {
+    let rest = text.strip_prefix(command).unwrap_or(text);
+    match rest.strip_prefix('@') {
+        Some(rest) => rest.find(char::is_whitespace).map_or("", |i| &rest[i..]),
+        None => rest,
+    }
+}
+
+/// Replaces the status message `status_id` with `text`, or with a `name` document when
+/// `text` is over Telegram's length limit.
+pub async fn edit_or_upload(
+    bot: &Client,
+    chat: Chat,
+    status_id: i32,
+    text: String,
+    name: &str,
+) -> Result<()> {
+    if text.encode_utf16().count() <= MESSAGE_MAX_LEN {
+        bot.edit_message(chat, status_id, text).await?;
+        return Ok(());
+    }
+
+    let size = text.len();
+    let mut stream = Cursor::new(text.into_bytes());
+    let uploaded = bot
+        .upload_stream(&mut stream, size, name.to_string())
+        .await?;
+    bot.send_message(
+        chat.clone(),
+        InputMessage::text("结果过长, 已作为文件发送")
+            .document(uploaded)
+            .mime_type("text/plain")
+            .reply_to(Some(status_id)),
+    )
+    .await?;
+    bot.delete_messages(chat, &[status_id]).await?;
+
+    Ok(())
+}
+
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Saves every session periodically, so new auth keys and update state survive a crash.
+pub async fn save_task(pool: Pool, bot: Client) -> Result<()> {
+    loop {
+        sleep(Duration::from_secs(SESSION_SAVE_INTERVAL)).await;
+        save_session(&pool, &bot);
+    }
+}
+
+pub fn save_session(pool: &Pool, bot: &Client) {
+    for account in pool.accounts() {
+        account.client.sync_update_state();
+        match vault::save(account.client.session(), &account.session) {
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("NOTE: failed to save the session, will sign out when done: {e}");
+            }
         }
     }
-    match bot.session().save_to_file(BOT_SESSION_FILE) {
+    bot.sync_update_state();
+    match vault::save(bot.session(), BOT_SESSION_FILE) {
         Ok(_) => {}
         Err(e) => {
             log::error!("NOTE: failed to save the session, will sign out when done: {e}");
@@ -0,0 +1,162 @@
+use std::{fs, path::Path};
+
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit},
+};
+use anyhow::{Result, anyhow, bail};
+use grammers_client::session::Session;
+use sha2::Sha256;
+
+use crate::{
+    defs::{ENV_PREFIX, SESSION_KDF_ROUNDS},
+    login::provided,
+};
+
+/// Encrypted session files start with this, followed by the salt, the nonce and the AES-256-GCM ciphertext.
+const MAGIC: &[u8] = b"CHECKBOT-SESSION-1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// The session passphrase from `CHECKBOT_SESSION_KEY` or the key file named by `CHECKBOT_SESSION_KEY_FILE`.
+fn passphrase() -> Result<Option<String>> {
+    Ok(provided(&format!("{}_SESSION_KEY", ENV_PREFIX))?.filter(|k| !k.is_empty()))
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Aes256Gcm {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, SESSION_KDF_ROUNDS, &mut key);
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+fn random<const N: usize>() -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    getrandom::getrandom(&mut buf).map_err(|e| anyhow!("getrandom: {}", e))?;
+    Ok(buf)
+}
+
+fn encrypt(passphrase: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let salt = random::<SALT_LEN>()?;
+    let nonce = random::<NONCE_LEN>()?;
+    let ciphertext = cipher(passphrase, &salt)
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .map_err(|_| anyhow!("encrypt session failed"))?;
+
+    Ok([MAGIC, &salt, &nonce, &ciphertext].concat())
+}
+
+fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn decrypt(path: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let Some(passphrase) = passphrase()? else {
+        bail!(
+            "{} is encrypted; set {1}_SESSION_KEY or {1}_SESSION_KEY_FILE",
+            path,
+            ENV_PREFIX
+        );
+    };
+    open(&passphrase, path, data)
+}
+
+fn open(passphrase: &str, path: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let data = &data[MAGIC.len()..];
+    if data.len() < SALT_LEN + NONCE_LEN {
+        bail!("{} is truncated", path);
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    // GCM authenticates the data, so a wrong key is told apart from a usable session.
+    cipher(passphrase, salt)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| {
+            anyhow!(
+                "cannot decrypt {}: wrong session key, or the file is corrupted",
+                path
+            )
+        })
+}
+
+/// Writes through a temporary file so a crash never leaves a half-written session behind.
+fn write(path: &str, data: &[u8]) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Loads `path`, decrypting it if needed, or starts a new session when the file doesn't exist.
+pub fn load(path: &str) -> Result<Session> {
+    if !Path::new(path).exists() {
+        return Ok(Session::new());
+    }
+    let data = fs::read(path)?;
+    let data = if is_encrypted(&data) {
+        decrypt(path, &data)?
+    } else {
+        data
+    };
+
+    Session::load(&data).map_err(|e| anyhow!("load {} failed: {:?}", path, e))
+}
+
+/// Saves `session` to `path`, encrypted when a session key is set.
+pub fn save(session: &Session, path: &str) -> Result<()> {
+    let data = session.save();
+    match passphrase()? {
+        Some(passphrase) => write(path, &encrypt(&passphrase, &data)?),
+        None => write(path, &data),
+    }
+}
+
+/// Encrypts every plaintext session in `paths` with the configured key, for the `encrypt-sessions` command.
+pub fn encrypt_sessions(paths: &[String]) -> Result<()> {
+    let Some(passphrase) = passphrase()? else {
+        bail!(
+            "set {0}_SESSION_KEY or {0}_SESSION_KEY_FILE to encrypt sessions",
+            ENV_PREFIX
+        );
+    };
+
+    for path in paths {
+        if !Path::new(path).exists() {
+            println!("{}: not found, skipped", path);
+            continue;
+        }
+        let data = fs::read(path)?;
+        if is_encrypted(&data) {
+            // Fails on a wrong key, so sessions never end up under different keys.
+            decrypt(path, &data)?;
+            println!("{}: already encrypted", path);
+            continue;
+        }
+        Session::load(&data).map_err(|e| anyhow!("load {} failed: {:?}", path, e))?;
+        write(path, &encrypt(&passphrase, &data)?)?;
+        println!("{}: encrypted", path);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_round_trips() {
+        let data = b"session bytes";
+        let sealed = encrypt("passphrase", data).unwrap();
+        assert!(is_encrypted(&sealed));
+        assert_eq!(open("passphrase", "test", &sealed).unwrap(), data);
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let sealed = encrypt("passphrase", b"session bytes").unwrap();
+        let err = open("other", "test", &sealed).unwrap_err();
+        assert!(err.to_string().contains("wrong session key"));
+        assert!(open("passphrase", "test", &sealed[..MAGIC.len() + 4]).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use grammers_client::{Client, InputMessage, Update, grammers_tl_types as tl, types::Message};
+use serde::Serialize;
+use tokio::time::sleep;
+
+use crate::{
+    check::CommonGroup,
+    config::MainConfig,
+    defs::WATCH_INTERVAL,
+    health::next_update,
+    history::{Diff, track, watch_diff},
+    pool::Pool,
+    utils::get_packed_user,
+    webhook::emit,
+};
+
+/// Sends `text(admin)` to every admin, so each alert can respect that admin's own scope.
+pub async fn notify_admins<F: Fn(i64) -> String>(bot: &Client, config: &MainConfig, text: F) {
+    for admin in config.role_holders() {
+        let result = match get_packed_user(bot, admin).await {
+            Ok(p) => bot
+                .send_message(p, InputMessage::text(text(admin)))
+                .await
+                .map(|_| ())
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::warn!("notify admin {} failed: {}", admin, e);
+        }
+    }
+}
+
+fn describe_groups(config: &MainConfig, admin: i64, list: &[CommonGroup]) -> String {
+    let vis = config.visibility(admin, admin, true);
+    let visible = list
+        .iter()
+        .filter(|g| vis.can_see(g))
+        .map(|g| format!("{} ({})", g.title, g.id))
+        .collect::<Vec<_>>();
+
+    if visible.len() == list.len() {
+        visible.join(", ")
+    } else {
+        format!("{} 个群", list.len())
+    }
+}
+
+/// `watchlist` webhook payload: the monitored groups a watched user joined or left.
+#[derive(Serialize)]
+struct WatchEvent<'a> {
+    user: i64,
+    joined: &'a [CommonGroup],
+    left: &'a [CommonGroup],
+}
+
+async fn alert_diff(bot: &Client, config: &MainConfig, user: i64, diff: &Diff) {
+    if diff.joined.is_empty() && diff.left.is_empty() {
+        return;
+    }
+    emit(
+        "watchlist",
+        WatchEvent {
+            user,
+            joined: &diff.joined,
+            left: &diff.left,
+        },
+    );
+    notify_admins(bot, config, |admin| {
+        let mut lines = vec![format!("监控用户 {} 的共同群发生变化", user)];
+        if !diff.joined.is_empty() {
+            lines.push(format!(
+                "加入: {}",
+                describe_groups(config, admin, &diff.joined)
+            ));
+        }
+        if !diff.left.is_empty() {
+            lines.push(format!(
+                "退出: {}",
+                describe_groups(config, admin, &diff.left)
+            ));
+        }
+        lines.join("\n")
+    })
+    .await;
+}
+
+/// Members added, joined or removed by a service message, as `(joined, left)`.
+pub fn action_members(msg: &Message) -> (Vec<i64>, Vec<i64>) {
+    let sender = msg.sender().map(|s| s.id());
+
+    match msg.action() {
+        Some(tl::enums::MessageAction::ChatAddUser(a)) => (a.users.clone(), Vec::new()),
+        Some(tl::enums::MessageAction::ChatJoinedByLink(_))
+        | Some(tl::enums::MessageAction::ChatJoinedByRequest) => {
+            (sender.into_iter().collect(), Vec::new())
+        }
+        Some(tl::enums::MessageAction::ChatDeleteUser(a)) => (Vec::new(), vec![a.user_id]),
+        _ => (Vec::new(), Vec::new()),
+    }
+}
+
+async fn handle_action(pool: &Pool, bot: &Client, msg: &Message) -> Result<()> {
+    let config = MainConfig::read_config();
+    let chat = msg.chat();
+    if !config.groups.contains(&chat.id()) {
+        return Ok(());
+    }
+
+    let (joined, left) = action_members(msg);
+    let group = [CommonGroup {
+        id: chat.id(),
+        title: chat.name().to_string(),
+        username: chat.username().map(|u| u.to_string()),
+    }];
+    for (user, join) in joined
+        .into_iter()
+        .map(|u| (u, true))
+        .chain(left.into_iter().map(|u| (u, false)))
+    {
+        if !config.watchlist.contains(&user) {
+            continue;
+        }
+        let verb = if join { "加入" } else { "退出" };
+        let none: &[CommonGroup] = &[];
+        emit(
+            "watchlist",
+            WatchEvent {
+                user,
+                joined: if join { &group } else { none },
+                left: if join { none } else { &group },
+            },
+        );
+        // Refresh the stored history so the periodic check doesn't alert twice.
+        if let Ok(lookup) = pool.common_groups(user, &config.groups).await {
+            watch_diff(user, &lookup);
+        }
+        // Alerts go to private chats, so only the admin's scope limits which names are shown.
+        notify_admins(bot, &config, |admin| {
+            let vis = config.visibility(admin, admin, true);
+            if vis.scope.as_ref().is_none_or(|s| s.contains(&chat.id())) {
+                format!(
+                    "监控用户 {} {}了群 {} ({})",
+                    user,
+                    verb,
+                    chat.name(),
+                    chat.id()
+                )
+            } else {
+                format!("监控用户 {} {}了一个监控群", user, verb)
+            }
+        })
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Listens to one userbot's own updates for members joining or leaving monitored groups.
+pub async fn watch_task(session: String, client: Client, pool: Pool, bot: Client) -> Result<()> {
+    loop {
+        if let Update::NewMessage(msg) = next_update(&session, &client).await
+            && msg.action().is_some()
+            && pool.handles(&session, msg.chat().id())
+            && let Err(e) = handle_action(&pool, &bot, &msg).await
+        {
+            log::warn!("watch: handle chat action failed: {}", e);
+        }
+    }
+}
+
+/// Re-checks every watched user periodically to catch changes no event was seen for.
+pub async fn poll_task(pool: Pool, bot: Client) -> Result<()> {
+    loop {
+        sleep(Duration::from_secs(WATCH_INTERVAL)).await;
+
+        let config = MainConfig::read_config();
+        for user in config.watchlist.iter().copied() {
+            match pool.common_groups(user, &config.groups).await {
+                Ok(lookup) => {
+                    track(user, &lookup);
+                    if let Some(diff) = watch_diff(user, &lookup) {
+                        alert_diff(&bot, &config, user, &diff).await;
+                    }
+                }
+                Err(e) => log::warn!("watch: check user {} failed: {}", user, e),
+            }
+        }
+    }
+}
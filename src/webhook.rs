@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer, Serialize, de};
+use sha2::Sha256;
+use tokio::time::sleep;
+
+use crate::{
+    check::{CommonGroup, group_ids},
+    config::MainConfig,
+    defs::WEBHOOK_RETRIES,
+    http::post,
+};
+
+/// An outbound webhook. `events` filters by event name (`watchlist`, `risk`,
+/// `config`, `audit`); empty means all of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    #[serde(deserialize_with = "http_url")]
+    pub url: String,
+    #[serde(default)]
+    pub secret: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+/// There is no TLS support, so anything but `http://` is refused when the config is
+/// loaded rather than failing on every delivery.
+fn http_url<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let url = String::deserialize(deserializer)?;
+    if !url.starts_with("http://") {
+        return Err(de::Error::custom(format!(
+            "webhook url must start with http:// (use a local relay for https): {}",
+            url
+        )));
+    }
+    Ok(url)
+}
+
+impl Webhook {
+    fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<T> {
+    event: &'static str,
+    time: DateTime<Utc>,
+    data: T,
+}
+
+/// Hex HMAC-SHA256 of `body`, sent as `X-Webhook-Signature: sha256=<hex>`.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+async fn deliver(hook: Webhook, event: &'static str, body: Vec<u8>) {
+    let headers = [
+        ("Content-Type", "application/json".to_string()),
+        ("X-Webhook-Event", event.to_string()),
+        (
+            "X-Webhook-Signature",
+            format!("sha256={}", sign(&hook.secret, &body)),
+        ),
+    ];
+
+    for attempt in 0..WEBHOOK_RETRIES {
+        match post(&hook.url, &headers, &body).await {
+            Ok(status) if (200..300).contains(&status) => return,
+            Ok(status) => log::warn!("webhook {} {}: status {}", event, hook.url, status),
+            Err(e) => log::warn!("webhook {} {}: {}", event, hook.url, e),
+        }
+        if attempt + 1 < WEBHOOK_RETRIES {
+            sleep(Duration::from_secs(1 << attempt)).await;
+        }
+    }
+    log::error!(
+        "webhook {} {}: giving up after {} attempts",
+        event,
+        hook.url,
+        WEBHOOK_RETRIES
+    );
+}
+
+/// Sends `data` to every webhook subscribed to `event`, in the background.
+pub fn emit<T: Serialize>(event: &'static str, data: T) {
+    let hooks = MainConfig::read_config()
+        .webhooks
+        .into_iter()
+        .filter(|h| h.wants(event))
+        .collect::<Vec<_>>();
+    if hooks.is_empty() {
+        return;
+    }
+
+    let body = match serde_json::to_vec(&Payload {
+        event,
+        time: Utc::now(),
+        data,
+    }) {
+        Ok(b) => b,
+        Err(e) => {
+            log::error!("webhook {}: serialize payload failed: {}", event, e);
+            return;
+        }
+    };
+    for hook in hooks {
+        tokio::spawn(deliver(hook, event, body.clone()));
+    }
+}
+
+#[derive(Serialize)]
+struct RiskAlert<'a> {
+    user: i64,
+    score: f64,
+    verdict: Option<String>,
+    flags: Vec<String>,
+    groups: &'a [CommonGroup],
+}
+
+/// Emits `risk` when `user`'s matched groups reach `risk.alert_score` or carry a flagged tag.
+pub fn risk_alert(user: i64, list: &[CommonGroup]) {
+    let rules = MainConfig::read_config().risk;
+    if !rules.enabled() {
+        return;
+    }
+
+    let a = rules.assess(&group_ids(list));
+    if a.flags.is_empty() && rules.alert_score.is_none_or(|s| a.score < s) {
+        return;
+    }
+    emit(
+        "risk",
+        RiskAlert {
+            user,
+            score: a.score,
+            verdict: a.verdict,
+            flags: a.flags,
+            groups: list,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{net::TcpListener, time::timeout};
+
+    use super::*;
+    use crate::http::{Response, read_request, write_response};
+
+    fn hook(url: String, events: &[&str]) -> Webhook {
+        Webhook {
+            url,
+            secret: "Jefe".to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn sign_is_hmac_sha256() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn only_http_urls_load() {
+        let parse = |url: &str| toml::from_str::<Webhook>(&format!("url = \"{}\"", url));
+        assert!(parse("http://127.0.0.1:9000/hook").is_ok());
+        assert!(parse("https://example.com/hook").is_err());
+        assert!(parse("example.com/hook").is_err());
+    }
+
+    #[test]
+    fn wants_filters_events() {
+        let all = hook(String::new(), &[]);
+        assert!(all.wants("watchlist") && all.wants("audit"));
+
+        let some = hook(String::new(), &["risk", "config"]);
+        assert!(some.wants("risk") && some.wants("config"));
+        assert!(!some.wants("audit"));
+    }
+
+    #[tokio::test]
+    async fn deliver_signs_and_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let body = br#"{"event":"risk"}"#.to_vec();
+        let sent = tokio::spawn(deliver(hook(url, &["risk"]), "risk", body.clone()));
+
+        for status in [500, 200] {
+            let (mut stream, _) = timeout(Duration::from_secs(5), listener.accept())
+                .await
+                .expect("no retry after a non-2xx response")
+                .unwrap();
+            let req = read_request(&mut stream).await.unwrap();
+            assert_eq!(req.method, "POST");
+            assert_eq!(req.path, "/hook");
+            assert_eq!(req.body, body);
+            assert_eq!(req.header("x-webhook-event"), Some("risk"));
+            assert_eq!(
+                req.header("x-webhook-signature"),
+                Some(format!("sha256={}", sign("Jefe", &body)).as_str())
+            );
+            write_response(&mut stream, Response::text(status, ""))
+                .await
+                .unwrap();
+        }
+
+        // Delivery stops once the retry is answered with a 2xx.
+        timeout(Duration::from_secs(5), sent)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}